@@ -0,0 +1,41 @@
+//! Measures the throughput of the SHA256 computation used to verify downloaded packages.
+//!
+//! `sha2` detects SHA-NI (x86_64) and the SHA2 crypto extensions (aarch64) at runtime and uses
+//! them when available. To compare against the portable implementation, run the benchmark twice:
+//!
+//! ```bash
+//! cargo bench --bench sha256
+//! cargo bench --bench sha256 --features force-soft-sha256
+//! ```
+//!
+//! Building with `RUSTFLAGS="-C target-cpu=native"` additionally removes the runtime detection.
+
+use rattler_digest::{Sha256Hash, compute_bytes_digest};
+use std::{hint::black_box, time::Instant};
+
+const BUFFER_SIZE: usize = 100 * 1024 * 1024;
+const ITERATIONS: u32 = 5;
+
+fn main() {
+    let buf = (0..BUFFER_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    let implementation = if cfg!(feature = "force-soft-sha256") {
+        "portable"
+    } else {
+        "accelerated (if supported by the CPU)"
+    };
+
+    // Warm up caches before measuring.
+    black_box(compute_bytes_digest::<sha2::Sha256>(black_box(&buf)));
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let digest: Sha256Hash = compute_bytes_digest::<sha2::Sha256>(black_box(&buf));
+        black_box(digest);
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    let throughput = BUFFER_SIZE as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64();
+    println!(
+        "sha256 {implementation}: {} MiB in {elapsed:?} ({throughput:.0} MiB/s)",
+        BUFFER_SIZE / 1024 / 1024
+    );
+}
@@ -0,0 +1,93 @@
+use miette::IntoDiagnostic;
+use opendal::{EntryMode, Operator};
+use rattler_conda_types::Platform;
+use std::{collections::HashSet, path::Path};
+
+/// Makes the mirrored `subdir` available under each of the given alias names.
+///
+/// For local destinations the alias is a symlink to the subdir. For all other destinations the
+/// contents of the subdir are copied, files that are no longer in the subdir are removed from
+/// the alias.
+pub(crate) async fn write_subdir_aliases(
+    op: &Operator,
+    local_root: Option<&Path>,
+    subdir: Platform,
+    aliases: &[String],
+) -> miette::Result<()> {
+    for alias in aliases {
+        match local_root {
+            #[cfg(unix)]
+            Some(root) => symlink_alias(root, subdir, alias)?,
+            _ => copy_alias(op, subdir, alias).await?,
+        }
+        tracing::info!("Made {} available as {}", subdir, alias);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_alias(root: &Path, subdir: Platform, alias: &str) -> miette::Result<()> {
+    let alias_path = root.join(alias);
+    match std::fs::symlink_metadata(&alias_path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if std::fs::read_link(&alias_path).into_diagnostic()? == Path::new(subdir.as_str()) {
+                return Ok(());
+            }
+            std::fs::remove_file(&alias_path).into_diagnostic()?;
+        }
+        Ok(_) => {
+            return Err(miette::miette!(
+                "Cannot create alias {} for {}, {} already exists",
+                alias,
+                subdir,
+                alias_path.display()
+            ));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).into_diagnostic(),
+    }
+    // Relative, so that the channel can be moved around.
+    std::os::unix::fs::symlink(subdir.as_str(), &alias_path).into_diagnostic()
+}
+
+async fn copy_alias(op: &Operator, subdir: Platform, alias: &str) -> miette::Result<()> {
+    let subdir_files = list_files(op, subdir.as_str()).await?;
+    let alias_files = list_files(op, alias).await?;
+
+    for file in &subdir_files {
+        // Packages never change once published, repodata files and the like do.
+        let is_package = file.ends_with(".conda") || file.ends_with(".tar.bz2");
+        if is_package && alias_files.contains(file) {
+            continue;
+        }
+        let from = format!("{}/{}", subdir.as_str(), file);
+        let to = format!("{alias}/{file}");
+        if op.info().full_capability().copy {
+            op.copy(&from, &to).await.into_diagnostic()?;
+        } else {
+            let bytes = op.read(&from).await.into_diagnostic()?;
+            op.write(&to, bytes).await.into_diagnostic()?;
+        }
+    }
+    for file in alias_files.difference(&subdir_files) {
+        op.delete(&format!("{alias}/{file}"))
+            .await
+            .into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Returns the paths of all files in `dir`, relative to `dir`.
+async fn list_files(op: &Operator, dir: &str) -> miette::Result<HashSet<String>> {
+    let prefix = format!("{dir}/");
+    let entries = match op.list_with(&prefix).recursive(true).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).into_diagnostic(),
+    };
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.metadata().mode() == EntryMode::FILE)
+        .filter_map(|entry| entry.path().strip_prefix(&prefix).map(str::to_string))
+        .collect())
+}
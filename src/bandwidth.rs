@@ -0,0 +1,50 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token bucket that limits the download bandwidth of all tasks of a mirror run.
+///
+/// The bucket holds at most one second worth of bytes. Taking more bytes than available puts the
+/// bucket into debt, which later callers have to wait out as well.
+pub(crate) struct BandwidthLimiter {
+    bytes_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl BandwidthLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            state: Mutex::new(BucketState {
+                available: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until `bytes` may be transferred without exceeding the limit.
+    pub(crate) async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(state.last_refill).as_secs_f64() * self.bytes_per_sec;
+            state.available = (state.available + refill).min(self.bytes_per_sec);
+            state.last_refill = now;
+            state.available -= bytes as f64;
+            if state.available < 0.0 {
+                Duration::from_secs_f64(-state.available / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
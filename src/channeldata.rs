@@ -0,0 +1,92 @@
+use miette::IntoDiagnostic;
+use opendal::Operator;
+use rattler_conda_types::{ChannelData, ChannelDataPackage, PackageRecord, Platform, RepoData};
+use std::collections::HashMap;
+
+/// Writes `channeldata.json` to the root of the destination.
+///
+/// The metadata is aggregated from the `repodata.json` files of the given subdirs in the
+/// destination, so subdirs that were mirrored in an earlier (resumed) run are included as well.
+pub(crate) async fn write_channeldata(op: &Operator, subdirs: &[Platform]) -> miette::Result<()> {
+    let mut channel_data = ChannelData {
+        channeldata_version: 1,
+        packages: HashMap::new(),
+        subdirs: Vec::new(),
+    };
+
+    let mut subdirs = subdirs.to_vec();
+    subdirs.sort_by_key(|subdir| subdir.as_str());
+    for subdir in subdirs {
+        let repodata_bytes = op
+            .read(&format!("{}/repodata.json", subdir.as_str()))
+            .await
+            .into_diagnostic()?;
+        let repodata: RepoData =
+            serde_json::from_slice(&repodata_bytes.to_vec()).into_diagnostic()?;
+        for record in repodata
+            .packages
+            .values()
+            .chain(repodata.conda_packages.values())
+        {
+            add_record(&mut channel_data, subdir, record);
+        }
+        channel_data.subdirs.push(subdir.to_string());
+    }
+
+    // Go through a `serde_json::Value` so that the package names are sorted.
+    let channel_data = serde_json::to_value(&channel_data).into_diagnostic()?;
+    let channel_data_bytes = serde_json::to_vec_pretty(&channel_data).into_diagnostic()?;
+    op.write("channeldata.json", channel_data_bytes)
+        .await
+        .into_diagnostic()?;
+    tracing::info!("Wrote channeldata.json");
+    Ok(())
+}
+
+fn add_record(channel_data: &mut ChannelData, subdir: Platform, record: &PackageRecord) {
+    let package = channel_data
+        .packages
+        .entry(record.name.as_normalized().to_string())
+        .or_insert_with(|| ChannelDataPackage {
+            has_activate_scripts: false,
+            has_deactivate_scripts: false,
+            binary_prefix: false,
+            description: None,
+            dev_url: Vec::new(),
+            doc_url: Vec::new(),
+            home: Vec::new(),
+            source_url: Vec::new(),
+            license: None,
+            has_post_link_scripts: false,
+            has_pre_link_scripts: false,
+            has_pre_unlink_scripts: false,
+            run_exports: HashMap::new(),
+            subdirs: Vec::new(),
+            summary: None,
+            text_prefix: false,
+            timestamp: None,
+            version: None,
+        });
+
+    if !package.subdirs.iter().any(|s| s == subdir.as_str()) {
+        package.subdirs.push(subdir.to_string());
+    }
+    if let Some(run_exports) = &record.run_exports {
+        package
+            .run_exports
+            .insert(record.version.version().clone(), run_exports.clone());
+    }
+    if let Some(timestamp) = record.timestamp {
+        let timestamp = timestamp.timestamp() as u64;
+        package.timestamp = Some(package.timestamp.map_or(timestamp, |t| t.max(timestamp)));
+    }
+    // The license is taken from the latest version of the package.
+    let is_latest = package
+        .version
+        .as_ref()
+        .is_none_or(|version| record.version.version() >= version);
+    if is_latest {
+        package.version = Some(record.version.version().clone());
+        package.license = record.license.clone();
+    }
+}
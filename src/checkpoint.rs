@@ -0,0 +1,59 @@
+use miette::IntoDiagnostic;
+use rattler_conda_types::{NamedChannelOrUrl, Platform};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Default location of the checkpoint file, relative to the current working directory.
+pub const DEFAULT_CHECKPOINT_PATH: &str = ".conda-mirror-checkpoint.json";
+
+/// State of an interrupted mirror run that can be picked up again with `--resume`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    pub source: String,
+    pub destination: String,
+    /// Subdirs that were mirrored completely, including their repodata.
+    pub completed: Vec<Platform>,
+    /// Subdirs that were not (completely) mirrored yet.
+    pub pending: Vec<Platform>,
+}
+
+impl Checkpoint {
+    pub fn new(source: &NamedChannelOrUrl, destination: &NamedChannelOrUrl) -> Self {
+        Self {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            completed: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Loads a checkpoint and makes sure it belongs to the given source and destination.
+    pub fn load(
+        path: &Path,
+        source: &NamedChannelOrUrl,
+        destination: &NamedChannelOrUrl,
+    ) -> miette::Result<Self> {
+        let checkpoint_str = std::fs::read_to_string(path)
+            .map_err(|e| miette::miette!("Could not read checkpoint {}: {}", path.display(), e))?;
+        let checkpoint: Checkpoint = serde_json::from_str(&checkpoint_str).into_diagnostic()?;
+        if checkpoint.source != source.to_string()
+            || checkpoint.destination != destination.to_string()
+        {
+            return Err(miette::miette!(
+                "Checkpoint {} was created for mirroring {} to {}, not {} to {}",
+                path.display(),
+                checkpoint.source,
+                checkpoint.destination,
+                source,
+                destination
+            ));
+        }
+        Ok(checkpoint)
+    }
+
+    pub fn write(&self, path: &Path) -> miette::Result<()> {
+        let checkpoint_str = serde_json::to_string_pretty(self).into_diagnostic()?;
+        std::fs::write(path, checkpoint_str)
+            .map_err(|e| miette::miette!("Could not write checkpoint {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,235 @@
+use opendal::{
+    Buffer, Error, ErrorKind, Metadata, Result,
+    raw::{
+        Access, Layer, LayeredAccess, OpCreateDir, OpDelete, OpList, OpRead, OpStat, OpWrite,
+        RpCreateDir, RpDelete, RpList, RpRead, RpStat, RpWrite, oio,
+    },
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// OpenDAL layer that stops calling the storage backend after `threshold` consecutive temporary
+/// errors and fails all operations immediately for `cooldown`.
+///
+/// It has to be added before the retry layer, so that every attempt counts and the permanent
+/// errors of an open breaker are not retried.
+#[derive(Clone)]
+pub(crate) struct CircuitBreakerLayer {
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerLayer {
+    pub(crate) fn new(threshold: usize, cooldown: Duration) -> Self {
+        Self {
+            breaker: Arc::new(CircuitBreaker {
+                threshold,
+                cooldown,
+                state: Mutex::new(BreakerState {
+                    consecutive_failures: 0,
+                    open_until: None,
+                }),
+            }),
+        }
+    }
+}
+
+impl<A: Access> Layer<A> for CircuitBreakerLayer {
+    type LayeredAccess = CircuitBreakerAccessor<A>;
+
+    fn layer(&self, inner: A) -> Self::LayeredAccess {
+        CircuitBreakerAccessor {
+            inner,
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    threshold: usize,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    consecutive_failures: usize,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Fails if the breaker is open. After the cooldown operations are let through again, a
+    /// single failure opens the breaker again until an operation succeeds.
+    fn check(&self) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(open_until) if Instant::now() < open_until => Err(Error::new(
+                ErrorKind::Unexpected,
+                format!(
+                    "circuit breaker is open after {} consecutive storage errors",
+                    state.consecutive_failures
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn record<T>(&self, result: Result<T>) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        match &result {
+            Ok(_) => {
+                state.consecutive_failures = 0;
+                state.open_until = None;
+            }
+            Err(err) if err.is_temporary() => {
+                state.consecutive_failures += 1;
+                if state.consecutive_failures >= self.threshold {
+                    if state
+                        .open_until
+                        .is_none_or(|open_until| open_until <= Instant::now())
+                    {
+                        tracing::warn!(
+                            "Opening the storage circuit breaker for {}s after {} consecutive errors",
+                            self.cooldown.as_secs(),
+                            state.consecutive_failures
+                        );
+                    }
+                    state.open_until = Some(Instant::now() + self.cooldown);
+                }
+            }
+            // Permanent errors like missing files say nothing about the health of the backend.
+            Err(_) => {}
+        }
+        result
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CircuitBreakerAccessor<A: Access> {
+    inner: A,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl<A: Access> LayeredAccess for CircuitBreakerAccessor<A> {
+    type Inner = A;
+    type Reader = CircuitBreakerWrapper<A::Reader>;
+    type BlockingReader = A::BlockingReader;
+    type Writer = CircuitBreakerWrapper<A::Writer>;
+    type BlockingWriter = A::BlockingWriter;
+    type Lister = CircuitBreakerWrapper<A::Lister>;
+    type BlockingLister = A::BlockingLister;
+    type Deleter = CircuitBreakerWrapper<A::Deleter>;
+    type BlockingDeleter = A::BlockingDeleter;
+
+    fn inner(&self) -> &Self::Inner {
+        &self.inner
+    }
+
+    async fn create_dir(&self, path: &str, args: OpCreateDir) -> Result<RpCreateDir> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.create_dir(path, args).await)
+    }
+
+    async fn read(&self, path: &str, args: OpRead) -> Result<(RpRead, Self::Reader)> {
+        self.breaker.check()?;
+        self.breaker
+            .record(self.inner.read(path, args).await)
+            .map(|(rp, r)| (rp, CircuitBreakerWrapper::new(r, self.breaker.clone())))
+    }
+
+    async fn write(&self, path: &str, args: OpWrite) -> Result<(RpWrite, Self::Writer)> {
+        self.breaker.check()?;
+        self.breaker
+            .record(self.inner.write(path, args).await)
+            .map(|(rp, w)| (rp, CircuitBreakerWrapper::new(w, self.breaker.clone())))
+    }
+
+    async fn stat(&self, path: &str, args: OpStat) -> Result<RpStat> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.stat(path, args).await)
+    }
+
+    async fn delete(&self) -> Result<(RpDelete, Self::Deleter)> {
+        self.breaker.check()?;
+        self.breaker
+            .record(self.inner.delete().await)
+            .map(|(rp, d)| (rp, CircuitBreakerWrapper::new(d, self.breaker.clone())))
+    }
+
+    async fn list(&self, path: &str, args: OpList) -> Result<(RpList, Self::Lister)> {
+        self.breaker.check()?;
+        self.breaker
+            .record(self.inner.list(path, args).await)
+            .map(|(rp, l)| (rp, CircuitBreakerWrapper::new(l, self.breaker.clone())))
+    }
+
+    fn blocking_read(&self, path: &str, args: OpRead) -> Result<(RpRead, Self::BlockingReader)> {
+        self.inner.blocking_read(path, args)
+    }
+
+    fn blocking_write(&self, path: &str, args: OpWrite) -> Result<(RpWrite, Self::BlockingWriter)> {
+        self.inner.blocking_write(path, args)
+    }
+
+    fn blocking_delete(&self) -> Result<(RpDelete, Self::BlockingDeleter)> {
+        self.inner.blocking_delete()
+    }
+
+    fn blocking_list(&self, path: &str, args: OpList) -> Result<(RpList, Self::BlockingLister)> {
+        self.inner.blocking_list(path, args)
+    }
+}
+
+pub(crate) struct CircuitBreakerWrapper<R> {
+    inner: R,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl<R> CircuitBreakerWrapper<R> {
+    fn new(inner: R, breaker: Arc<CircuitBreaker>) -> Self {
+        Self { inner, breaker }
+    }
+}
+
+impl<R: oio::Read> oio::Read for CircuitBreakerWrapper<R> {
+    async fn read(&mut self) -> Result<Buffer> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.read().await)
+    }
+}
+
+impl<R: oio::Write> oio::Write for CircuitBreakerWrapper<R> {
+    async fn write(&mut self, bs: Buffer) -> Result<()> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.write(bs).await)
+    }
+
+    async fn close(&mut self) -> Result<Metadata> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.close().await)
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.inner.abort().await
+    }
+}
+
+impl<R: oio::List> oio::List for CircuitBreakerWrapper<R> {
+    async fn next(&mut self) -> Result<Option<oio::Entry>> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.next().await)
+    }
+}
+
+impl<R: oio::Delete> oio::Delete for CircuitBreakerWrapper<R> {
+    fn delete(&mut self, path: &str, args: OpDelete) -> Result<()> {
+        self.inner.delete(path, args)
+    }
+
+    async fn flush(&mut self) -> Result<usize> {
+        self.breaker.check()?;
+        self.breaker.record(self.inner.flush().await)
+    }
+}
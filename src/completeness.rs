@@ -0,0 +1,74 @@
+use crate::diff::RepoDataDiff;
+use rattler_conda_types::Platform;
+
+/// Result of comparing the packages the configuration selects from the source with the package
+/// files in the destination.
+#[derive(Debug, Clone, Default)]
+pub struct CompletenessReport {
+    /// Number of packages that are selected from the source.
+    pub expected: usize,
+    /// Number of package files in the destination.
+    pub present: usize,
+    /// Selected packages that don't exist in the destination, as `subdir/filename`.
+    pub missing: Vec<String>,
+    /// Package files in the destination that are not selected, as `subdir/filename`.
+    pub unexpected: Vec<String>,
+}
+
+impl CompletenessReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Adds the packages of `subdir` from its diff.
+    pub(crate) fn add_subdir(
+        &mut self,
+        subdir: Platform,
+        diff: &RepoDataDiff,
+        expected: usize,
+        present: usize,
+    ) {
+        let path = |filename: &String| format!("{}/{}", subdir.as_str(), filename);
+        self.expected += expected;
+        self.present += present;
+        let mut missing = diff.only_in_source.keys().map(path).collect::<Vec<_>>();
+        missing.sort();
+        self.missing.extend(missing);
+        let mut unexpected = diff
+            .only_in_dest
+            .keys()
+            .chain(&diff.untracked_in_dest)
+            .map(path)
+            .collect::<Vec<_>>();
+        unexpected.sort();
+        self.unexpected.extend(unexpected);
+    }
+}
+
+/// Formats the report for printing.
+pub fn format_completeness_report(report: &CompletenessReport) -> String {
+    let mut output = format!(
+        "{:<11} {:>8}\n{:<11} {:>8}\n{:<11} {:>8}\n{:<11} {:>8}\n",
+        "Expected:",
+        report.expected,
+        "Present:",
+        report.present,
+        "Missing:",
+        report.missing.len(),
+        "Unexpected:",
+        report.unexpected.len(),
+    );
+    if !report.missing.is_empty() {
+        output.push_str("\nMissing packages:\n");
+        for path in &report.missing {
+            output.push_str(&format!("  {path}\n"));
+        }
+    }
+    if !report.unexpected.is_empty() {
+        output.push_str("\nUnexpected packages:\n");
+        for path in &report.unexpected {
+            output.push_str(&format!("  {path}\n"));
+        }
+    }
+    output
+}
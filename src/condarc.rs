@@ -0,0 +1,67 @@
+use miette::IntoDiagnostic;
+use rattler_conda_types::NamedChannelOrUrl;
+use serde::Deserialize;
+use std::path::PathBuf;
+use url::Url;
+
+/// The parts of a `.condarc` that are relevant for authenticating against the source channel.
+#[derive(Deserialize, Debug, Default)]
+struct Condarc {
+    channel_alias: Option<Url>,
+    #[serde(default)]
+    channel_settings: Vec<ChannelSettings>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChannelSettings {
+    channel: String,
+    token: Option<String>,
+}
+
+/// Location of the `.condarc`, either from `$CONDARC` or `~/.condarc`.
+fn condarc_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("CONDARC") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::home_dir().map(|home| home.join(".condarc"))
+}
+
+/// Looks up the token for `channel` in the `channel_settings` of the user's `.condarc`.
+///
+/// Named channels match by name, URLs match if they start with the configured channel, which may
+/// be relative to the `channel_alias`.
+pub(crate) fn token_for_channel(channel: &NamedChannelOrUrl) -> miette::Result<Option<String>> {
+    let Some(path) = condarc_path().filter(|path| path.is_file()) else {
+        return Ok(None);
+    };
+    let condarc_str = std::fs::read_to_string(&path).into_diagnostic()?;
+    let condarc: Condarc = serde_yml::from_str(&condarc_str)
+        .map_err(|e| miette::miette!("Could not parse {}: {}", path.display(), e))?;
+    tracing::debug!("Looking up token for {} in {}", channel, path.display());
+
+    let token = condarc
+        .channel_settings
+        .iter()
+        .filter(|settings| match channel {
+            NamedChannelOrUrl::Name(name) => &settings.channel == name,
+            NamedChannelOrUrl::Url(url) => {
+                let settings_url = if settings.channel.contains("://") {
+                    settings.channel.clone()
+                } else if let Some(channel_alias) = &condarc.channel_alias {
+                    format!(
+                        "{}/{}",
+                        channel_alias.as_str().trim_end_matches('/'),
+                        settings.channel
+                    )
+                } else {
+                    return false;
+                };
+                url.as_str()
+                    .trim_end_matches('/')
+                    .starts_with(settings_url.trim_end_matches('/'))
+            }
+            NamedChannelOrUrl::Path(_) => false,
+        })
+        .find_map(|settings| settings.token.clone());
+    Ok(token)
+}
@@ -1,19 +1,26 @@
 use miette::IntoDiagnostic;
 use rattler_conda_types::{
-    ChannelConfig, MatchSpec, Matches, NamedChannelOrUrl, NamelessMatchSpec, PackageRecord,
-    Platform,
+    Channel, ChannelConfig, MatchSpec, Matches, NamedChannelOrUrl, NamelessMatchSpec, PackageName,
+    PackageRecord, Platform,
 };
 use serde::{Deserialize, Deserializer};
-use std::{env::current_dir, path::PathBuf, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    env::current_dir,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
 
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use clap_verbosity_flag::Verbosity;
 use url::Url;
 
 /* -------------------------------------------- CLI ------------------------------------------- */
 
 /// The conda-mirror CLI.
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct CliConfig {
     /// The source channel to mirror from.
@@ -24,10 +31,45 @@ pub struct CliConfig {
     #[arg(long, requires_all = ["source"])]
     pub destination: Option<NamedChannelOrUrl>,
 
-    /// The subdirectories to mirror.
-    #[arg(long)]
+    /// What kind of directory the source is [default: channel].
+    #[arg(long, value_enum)]
+    pub source_type: Option<SourceType>,
+
+    /// Source to fetch from if the source can't be reached, can be repeated.
+    ///
+    /// Fallback sources are tried in order. Replaces `fallback-sources` from the configuration
+    /// file.
+    #[arg(long = "fallback-source", value_name = "SOURCE", action = clap::ArgAction::Append)]
+    pub fallback_sources: Vec<NamedChannelOrUrl>,
+
+    /// The subdirectories to mirror. Can be repeated or take several subdirs separated by spaces.
+    #[arg(long, action = clap::ArgAction::Append, num_args = 1..)]
     pub subdir: Option<Vec<Platform>>,
 
+    /// Only mirror subdirs matching this glob, e.g. `linux-*`, can be repeated.
+    ///
+    /// Replaces `include-subdirs` from the configuration file.
+    #[arg(long, value_name = "GLOB", action = clap::ArgAction::Append)]
+    pub include_subdir: Vec<GlobPattern>,
+
+    /// Don't mirror subdirs matching this glob, e.g. `win-*`, can be repeated.
+    ///
+    /// Replaces `exclude-subdirs` from the configuration file.
+    #[arg(long, value_name = "GLOB", action = clap::ArgAction::Append)]
+    pub exclude_subdir: Vec<GlobPattern>,
+
+    /// Only mirror packages matching this MatchSpec, can be repeated.
+    ///
+    /// Replaces `include` from the configuration file.
+    #[arg(long, value_name = "MATCHSPEC", action = clap::ArgAction::Append)]
+    pub include: Vec<MatchSpec>,
+
+    /// Don't mirror packages matching this MatchSpec, can be repeated.
+    ///
+    /// Replaces `exclude` from the configuration file.
+    #[arg(long, value_name = "MATCHSPEC", action = clap::ArgAction::Append)]
+    pub exclude: Vec<MatchSpec>,
+
     /// The configuration file to use.
     #[arg(short, long)]
     pub config: Option<PathBuf>,
@@ -56,6 +98,33 @@ pub struct CliConfig {
     #[arg(long, requires_all = ["s3_endpoint_url_destination", "s3_region_destination"])]
     pub s3_force_path_style_destination: Option<bool>,
 
+    /// Server-side encryption of the packages uploaded to the S3 destination.
+    #[arg(long, value_name = "ALGORITHM")]
+    pub s3_server_side_encryption: Option<SseAlgorithm>,
+
+    /// The KMS key ID used with `--s3-server-side-encryption aws:kms`.
+    #[arg(long)]
+    pub s3_kms_key_id: Option<String>,
+
+    /// The storage class of the packages uploaded to the S3 destination, e.g. `STANDARD_IA` [default: STANDARD].
+    #[arg(long)]
+    pub s3_storage_class: Option<String>,
+
+    /// Checksum that S3 verifies when packages are uploaded to the S3 destination.
+    #[arg(long, value_name = "ALGORITHM")]
+    pub s3_checksum_algorithm: Option<S3ChecksumAlgorithm>,
+
+    /// ARN of an IAM role to assume for the S3 destination.
+    ///
+    /// The credentials for assuming the role are taken from the environment, e.g. the instance
+    /// profile on EC2 or the task role on ECS, if no access key is set.
+    #[arg(long)]
+    pub s3_role_arn: Option<String>,
+
+    /// The external ID used when assuming `--s3-role-arn`.
+    #[arg(long, requires = "s3_role_arn")]
+    pub s3_external_id: Option<String>,
+
     /// The access key ID for the S3 bucket.
     #[arg(long, env = "S3_ACCESS_KEY_ID_SOURCE", requires_all = ["s3_secret_access_key_source"])]
     pub s3_access_key_id_source: Option<String>,
@@ -80,9 +149,387 @@ pub struct CliConfig {
     #[arg(long, env = "S3_SESSION_TOKEN_DESTINATION", requires_all = ["s3_access_key_id_destination", "s3_secret_access_key_destination"])]
     pub s3_session_token_destination: Option<String>,
 
+    /// Upload to the http(s) destination using WebDAV.
+    #[arg(long)]
+    pub webdav: bool,
+
+    /// The username for HTTP Basic authentication against the WebDAV destination.
+    #[arg(long, requires_all = ["webdav_password"])]
+    pub webdav_username: Option<String>,
+
+    /// The password for HTTP Basic authentication against the WebDAV destination.
+    #[arg(long, env = "WEBDAV_PASSWORD", requires_all = ["webdav_username"])]
+    pub webdav_password: Option<WebDavPassword>,
+
+    /// The application key ID for B2 destinations.
+    #[arg(long, env = "B2_APPLICATION_KEY_ID")]
+    pub b2_application_key_id: Option<String>,
+
+    /// The application key for B2 destinations.
+    #[arg(long, env = "B2_APPLICATION_KEY")]
+    pub b2_application_key: Option<B2ApplicationKey>,
+
+    /// The ID of the bucket of B2 destinations.
+    #[arg(long)]
+    pub b2_bucket_id: Option<String>,
+
+    /// The SSH private key used for SFTP destinations.
+    #[arg(long, env = "SSH_PRIVATE_KEY_PATH")]
+    pub sftp_private_key_path: Option<PathBuf>,
+
+    /// Whether to verify the host key of SFTP destinations against `~/.ssh/known_hosts` [default: true].
+    #[arg(long)]
+    pub sftp_host_key_check: Option<bool>,
+
+    /// Short name of the source channel used in log messages and progress bars.
+    #[arg(long = "source-channel-display-name", value_name = "NAME")]
+    pub source_display_name: Option<String>,
+
+    /// Bearer token that is sent to the source channel in the `Authorization` header.
+    #[arg(long, env = "CONDA_BEARER_TOKEN")]
+    pub bearer_token: Option<BearerToken>,
+
+    /// API key of a Quetz server that is sent to the source channel.
+    #[arg(long, env = "QUETZ_TOKEN")]
+    pub quetz_token: Option<QuetzToken>,
+
+    /// Where the Quetz token is sent [default: header].
+    #[arg(long, value_enum)]
+    pub quetz_token_location: Option<QuetzTokenLocation>,
+
+    /// anaconda.org token that is appended as `?token=` to requests to anaconda.org and conda.io.
+    #[arg(long, env = "ANACONDA_TOKEN")]
+    pub conda_token: Option<CondaToken>,
+
+    /// Append the changes to repodata.json to a JLAP file (experimental).
+    #[arg(long)]
+    pub write_jlap: bool,
+
+    /// Write a `<package>.sha256` file with the SHA256 of each package next to it.
+    #[arg(long = "write-checksums-separately")]
+    pub write_sha256_sidecar: bool,
+
+    /// Write a `manifest.json` with the checksums and size of all packages to each subdir.
+    #[arg(long)]
+    pub write_manifest: bool,
+
+    /// Write the paths of all mirrored packages (`{subdir}/{filename}`) to this file, one per line.
+    ///
+    /// With several destinations, one file per destination is written with its index appended to
+    /// the file name, e.g. `packages-0.txt` and `packages-1.txt`.
+    #[arg(long = "write-package-listing", value_name = "PATH")]
+    pub package_listing_path: Option<PathBuf>,
+
+    /// Timeout in seconds for establishing a connection to the source.
+    #[arg(long = "connect-timeout", value_name = "SECONDS")]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// Timeout in seconds between two reads from the source [default: 120].
+    #[arg(long = "read-timeout", value_name = "SECONDS")]
+    pub read_timeout_secs: Option<u64>,
+
+    /// Timeout in seconds for a complete request to the source, including the response body.
+    #[arg(long = "request-timeout", value_name = "SECONDS")]
+    pub request_timeout_secs: Option<u64>,
+
+    /// Minimum expected download speed in bytes per millisecond. Package downloads time out after
+    /// the read timeout plus the package size divided by this speed.
+    #[arg(long = "connection-timeout-per-byte", value_name = "BYTES_PER_MS")]
+    pub bytes_per_ms_timeout: Option<f64>,
+
+    /// Maximum number of redirects that are followed for requests to the source [default: 10].
+    #[arg(long, value_name = "N")]
+    pub source_max_redirects: Option<usize>,
+
+    /// Use HTTP/2 without negotiating it first, for servers that support HTTP/2 without TLS.
+    #[arg(long, conflicts_with = "disable_http2")]
+    pub enable_http2: bool,
+
+    /// Only use HTTP/1.1, for servers or proxies with broken HTTP/2 support.
+    #[arg(long)]
+    pub disable_http2: bool,
+
+    /// User-Agent header of the requests to the source [default: conda-mirror/<version>].
+    #[arg(long)]
+    pub user_agent: Option<String>,
+
+    /// Limit the total download speed of all packages to this many bytes per second.
+    #[arg(long = "bandwidth-limit", value_name = "BYTES_PER_SEC")]
+    pub bandwidth_limit_bytes_per_sec: Option<u64>,
+
+    /// Wait a fixed number of seconds between retries instead of backing off exponentially.
+    #[arg(long = "retry-delay", value_name = "SECONDS")]
+    pub retry_delay_override_secs: Option<u64>,
+
+    /// Maximum number of retries of failed requests and storage operations [default: 12 for requests, 3 for storage operations].
+    #[arg(long, value_name = "N")]
+    pub retry_max_retries: Option<u32>,
+
+    /// Delay in milliseconds before the first retry [default: 1000].
+    #[arg(long, value_name = "MILLISECONDS")]
+    pub retry_initial_interval_ms: Option<u64>,
+
+    /// Factor by which the delay grows with every retry [default: 2].
+    #[arg(long, value_name = "FACTOR")]
+    pub retry_multiplier: Option<u32>,
+
+    /// Stop calling the destination storage for a while after repeated errors instead of retrying.
+    #[arg(long)]
+    pub enable_opendal_circuit_breaker: bool,
+
+    /// Consecutive storage errors after which the circuit breaker opens [default: 5].
+    #[arg(long, value_name = "ERRORS")]
+    pub circuit_breaker_threshold: Option<usize>,
+
+    /// Time in seconds before storage operations are tried again after the circuit breaker opened [default: 30].
+    #[arg(long, value_name = "SECONDS")]
+    pub circuit_breaker_cooldown_secs: Option<u64>,
+
+    /// Timeout in milliseconds for the requests probing which subdirs exist in the source [default: 5000].
+    ///
+    /// The requests are not retried, a subdir that doesn't respond in time is skipped.
+    #[arg(long = "source-timeout-ms", value_name = "MILLISECONDS")]
+    pub source_probe_timeout_ms: Option<u64>,
+
+    /// Maximum fraction of the existing packages in a subdir that may be deleted [default: 0.2].
+    #[arg(long, value_name = "FRACTION")]
+    pub max_delete_fraction: Option<f64>,
+
+    /// Delete packages even if this exceeds `--max-delete-fraction`.
+    #[arg(long)]
+    pub force_delete: bool,
+
+    /// Move deleted packages to `--trash-prefix` in the destination instead of removing them.
+    #[arg(long)]
+    pub soft_delete: bool,
+
+    /// Where `--soft-delete` moves packages to in the destination [default: _trash/].
+    #[arg(long, value_name = "PREFIX")]
+    pub trash_prefix: Option<String>,
+
+    /// Only delete packages after they were missing in the source for RUNS consecutive runs.
+    ///
+    /// Requires `--mirror-stats-db`, which keeps track of the missing packages.
+    #[arg(long = "smart-delete", value_name = "RUNS")]
+    pub smart_delete_runs: Option<usize>,
+
+    /// Never remove packages from the repodata of the destination once they were mirrored.
+    ///
+    /// Packages removed from the source are kept and listed in `removed` of the repodata.
+    #[arg(long)]
+    pub preserve_removed: bool,
+
+    /// Fail if a package to mirror has no SHA256 in the repodata instead of only warning.
+    #[arg(long)]
+    pub fail_on_missing_checksums: bool,
+
+    /// Download all packages again and overwrite the ones that already exist in the destination.
+    #[arg(long)]
+    pub force_redownload: bool,
+
+    /// Only delete packages that are not selected anymore, don't add any.
+    ///
+    /// Packages that would be added are left out of the written repodata.
+    #[arg(long = "delete-removed-only")]
+    pub delete_only: bool,
+
+    /// Print a tab-separated row for every added, deleted and skipped package to stdout.
+    #[arg(long)]
+    pub output_tsv: bool,
+
+    /// Print only the packages that were added (`+`) or deleted (`-`) in this run to stdout.
+    #[arg(long)]
+    pub output_changed_packages_only: bool,
+
+    /// Write the output of `--output-changed-packages-only` to this file instead of stdout.
+    #[arg(long, value_name = "PATH", requires = "output_changed_packages_only")]
+    pub output_file: Option<PathBuf>,
+
+    /// Only mirror packages built after this point in time (RFC 3339, e.g. `2025-01-01T00:00:00Z`).
+    ///
+    /// Older packages that already exist in the destination are kept.
+    #[arg(long, value_name = "DATETIME")]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Delete packages built more than this many days ago from the destination.
+    ///
+    /// Unlike `--since`, packages that already exist in the destination are deleted as well.
+    #[arg(long, value_name = "DAYS")]
+    pub channel_prune_age_days: Option<u32>,
+
+    /// Only mirror the first N selected packages of each subdir by filename, e.g. for smoke tests.
+    #[arg(long, value_name = "N")]
+    pub max_packages: Option<usize>,
+
+    /// Also mirror the dependencies of the selected packages, recursively.
+    ///
+    /// Dependencies are only looked up in the same subdir.
+    #[arg(long)]
+    pub with_deps: bool,
+
+    /// Don't mirror pre-releases, i.e. versions with an `a`, `alpha`, `b`, `beta`, `rc` or `dev`
+    /// part such as `1.0rc1`. A trailing letter like in `2024b` is not a pre-release.
+    #[arg(long)]
+    pub exclude_prerelease: bool,
+
+    /// Shell command that renames the packages in the destination.
+    ///
+    /// It gets the filenames of the packages of a subdir on stdin, one per line, and has to print
+    /// the new filenames in the same order, e.g. `sed 's/^/internal-/'`.
+    #[arg(long = "package-name-transform", value_name = "COMMAND")]
+    pub package_name_transform_command: Option<String>,
+
+    /// Fetch the repodata of all subdirs before downloading any packages.
+    #[arg(long)]
+    pub preload_repodata: bool,
+
+    /// Resume an interrupted run, skipping the subdirs that were already mirrored completely.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// The checkpoint file written when a run is interrupted [default: .conda-mirror-checkpoint.json].
+    #[arg(long)]
+    pub checkpoint_file: Option<PathBuf>,
+
+    /// Sign the metadata of mirrored packages and write a `.sig` file next to each package.
+    #[arg(long)]
+    pub sign_packages: bool,
+
+    /// The hex-encoded ed25519 private key used for signing packages.
+    #[arg(long)]
+    pub signing_key_path: Option<PathBuf>,
+
+    /// The id of the signing key that is written to the `.sig` files.
+    #[arg(long)]
+    pub signing_key_id: Option<String>,
+
+    /// Serve Prometheus metrics on this port while mirroring.
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// POST a JSON summary of every run to this URL.
+    #[arg(long, value_name = "URL")]
+    pub webhook_url: Option<Url>,
+
+    /// Only call `--webhook-url` if the run failed.
+    #[arg(long)]
+    pub webhook_on_failure_only: bool,
+
+    /// Record the statistics of every mirror run in this SQLite database.
+    #[arg(long, value_name = "PATH")]
+    pub mirror_stats_db: Option<PathBuf>,
+
+    /// Print the last N runs from the statistics database instead of mirroring.
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10")]
+    pub show_stats_history: Option<u32>,
+
+    /// Check the size and SHA256 of the packages in the destination against its repodata instead
+    /// of mirroring.
+    #[arg(long, conflicts_with = "interval_secs")]
+    pub verify_only: bool,
+
+    /// Check that the destination contains every package selected from the source instead of
+    /// mirroring.
+    ///
+    /// Only checks whether the package files exist, which is faster than `--verify-only`.
+    #[arg(long, conflicts_with_all = ["interval_secs", "verify_only"])]
+    pub check_completeness: bool,
+
+    /// Keep running and mirror the channel again every SECONDS.
+    #[arg(long = "interval", value_name = "SECONDS")]
+    pub interval_secs: Option<u64>,
+
+    /// File to write the process ID to when running with `--interval`.
+    #[arg(long, requires = "interval_secs")]
+    pub pid_file: Option<PathBuf>,
+
     // todo: add --force option
     #[command(flatten)]
     pub verbose: Verbosity,
+
+    /// Format of the log output.
+    #[arg(long, value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Convert the `.tar.bz2` packages of a local channel to `.conda` packages.
+    #[command(alias = "migrate-from-tar-bz2")]
+    Migrate(MigrateArgs),
+    /// List the destination backends that are available in this binary.
+    #[command(alias = "list-available-backends")]
+    ListBackends,
+    /// Check the configuration without mirroring anything.
+    ///
+    /// The configuration file and CLI options are parsed and the channels are resolved, no network
+    /// requests are made.
+    Validate,
+    /// Write an annotated example configuration file.
+    #[command(alias = "init")]
+    GenerateConfig(GenerateConfigArgs),
+    /// Show which packages of a subdir the include/exclude patterns select, without mirroring.
+    TestFilters(TestFiltersArgs),
+    /// Print presigned download URLs for the packages of a subdir in an S3 destination.
+    GeneratePresignedUrls(GeneratePresignedUrlsArgs),
+    /// Print a shell completion script to stdout.
+    #[command(hide = true)]
+    GenerateCompletions(GenerateCompletionsArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GenerateCompletionsArgs {
+    /// The shell to generate completions for.
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct TestFiltersArgs {
+    /// The subdir whose repodata the patterns are applied to.
+    #[arg(long)]
+    pub subdir: Platform,
+
+    /// Read the repodata from this repodata.json instead of fetching it from the source.
+    #[arg(long, value_name = "REPODATA_JSON")]
+    pub source_from_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GeneratePresignedUrlsArgs {
+    /// The subdir of the packages.
+    pub subdir: Platform,
+
+    /// Only generate a URL for this package, e.g. `numpy-2.3.0-py313h17eae1a_0.conda`.
+    pub package: Option<String>,
+
+    /// Number of seconds the URLs are valid for, at most 7 days.
+    #[arg(long, default_value_t = 3600, value_parser = clap::value_parser!(u64).range(1..=604800))]
+    pub expires_in: u64,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GenerateConfigArgs {
+    /// File to write the example configuration to, it is printed to stdout if unset.
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct MigrateArgs {
+    /// The local channel to migrate. Defaults to the destination from the configuration file.
+    #[arg(long)]
+    pub destination: Option<NamedChannelOrUrl>,
+
+    /// The subdirectories to migrate. Defaults to all subdirectories of the channel.
+    #[arg(long, action = clap::ArgAction::Append, num_args = 1..)]
+    pub subdir: Option<Vec<Platform>>,
+
+    /// Delete the `.tar.bz2` packages after converting them.
+    #[arg(long)]
+    pub delete_originals: bool,
 }
 
 #[derive(Clone)]
@@ -108,8 +555,154 @@ impl std::fmt::Debug for S3Credentials {
     }
 }
 
+#[derive(Clone)]
+pub struct WebDavCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for WebDavCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebDavCredentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct WebDavPassword(pub String);
+
+impl std::fmt::Debug for WebDavPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WebDavPassword(***)")
+    }
+}
+
+impl From<String> for WebDavPassword {
+    fn from(value: String) -> Self {
+        WebDavPassword(value)
+    }
+}
+
+#[derive(Clone)]
+pub struct B2Credentials {
+    pub application_key_id: String,
+    pub application_key: String,
+}
+
+impl std::fmt::Debug for B2Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("B2Credentials")
+            .field("application_key_id", &self.application_key_id)
+            .field("application_key", &"***")
+            .finish()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct B2ApplicationKey(pub String);
+
+impl std::fmt::Debug for B2ApplicationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("B2ApplicationKey(***)")
+    }
+}
+
+impl From<String> for B2ApplicationKey {
+    fn from(value: String) -> Self {
+        B2ApplicationKey(value)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct BearerToken(pub String);
+
+impl std::fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BearerToken(***)")
+    }
+}
+
+impl From<String> for BearerToken {
+    fn from(value: String) -> Self {
+        BearerToken(value)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct QuetzToken(pub String);
+
+impl std::fmt::Debug for QuetzToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("QuetzToken(***)")
+    }
+}
+
+impl From<String> for QuetzToken {
+    fn from(value: String) -> Self {
+        QuetzToken(value)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct CondaToken(pub String);
+
+impl std::fmt::Debug for CondaToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CondaToken(***)")
+    }
+}
+
+impl From<String> for CondaToken {
+    fn from(value: String) -> Self {
+        CondaToken(value)
+    }
+}
+
+/// How a Quetz token is passed to the source channel.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuetzTokenLocation {
+    /// Send the token in the `X-Quetz-API-Key` header.
+    #[default]
+    Header,
+    /// Embed the token in the URL as `/t/<token>/...`.
+    Url,
+}
+
+/// Layout of the source.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceType {
+    /// A conda channel with a `repodata.json` per subdir.
+    #[default]
+    Channel,
+    /// A local package cache like `~/.conda/pkgs` with the packages of all subdirs in one
+    /// directory and no repodata. The repodata is generated from the packages.
+    PackageCache,
+}
+
+/// Format of the log output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per event, for log aggregation systems.
+    Json,
+}
+
 /* -------------------------------------------- YAML ------------------------------------------- */
 
+/// Example configuration file with all supported options and their defaults.
+pub const EXAMPLE_CONFIG: &str = include_str!("example-config.yml");
+
 #[derive(Debug, Clone)]
 pub struct GlobPattern(glob::Pattern);
 
@@ -119,9 +712,15 @@ impl<'de> Deserialize<'de> for GlobPattern {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        glob::Pattern::from_str(&s)
-            .map(GlobPattern)
-            .map_err(serde::de::Error::custom)
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for GlobPattern {
+    type Err = glob::PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        glob::Pattern::from_str(s).map(GlobPattern)
     }
 }
 
@@ -165,25 +764,93 @@ pub enum PackageConfig {
         // TODO: use regular glob once https://github.com/conda/rattler/issues/1239 is done
         name_glob: GlobPattern,
         matchspec: Option<NamelessMatchSpecWrapper>,
+        /// Additionally requires the build string to match, e.g. `py311*`.
+        build_glob: Option<GlobPattern>,
+    },
+    /// Matches packages whose build string contains the given string, ignoring case.
+    #[serde(rename_all = "kebab-case")]
+    BuildStringContains {
+        #[serde(alias = "build_contains")]
+        build_contains: String,
+    },
+    /// Matches packages whose name is in the given list, e.g. from `conda env export`.
+    NameIn {
+        names: HashSet<PackageName>,
+    },
+    /// Matches like a MatchSpec, the dependencies of matching packages are mirrored as well.
+    #[serde(rename_all = "kebab-case")]
+    WithDeps {
+        with_deps: MatchSpecWrapper,
     },
     MatchSpec(MatchSpecWrapper),
 }
 
+impl From<MatchSpec> for PackageConfig {
+    fn from(matchspec: MatchSpec) -> Self {
+        PackageConfig::MatchSpec(MatchSpecWrapper(matchspec))
+    }
+}
+
 impl PackageConfig {
     pub(crate) fn matches(&self, package_record: PackageRecord) -> bool {
         match self {
             PackageConfig::PackageGlob {
                 name_glob,
                 matchspec,
+                build_glob,
             } => {
-                let name_match = name_glob.0.matches(package_record.name.as_normalized());
+                name_glob.0.matches(package_record.name.as_normalized())
+                    && matchspec
+                        .as_ref()
+                        .is_none_or(|matchspec| matchspec.0.matches(&package_record))
+                    && build_glob
+                        .as_ref()
+                        .is_none_or(|build_glob| build_glob.0.matches(&package_record.build))
+            }
+            PackageConfig::BuildStringContains { build_contains } => package_record
+                .build
+                .to_lowercase()
+                .contains(&build_contains.to_lowercase()),
+            PackageConfig::NameIn { names } => names.contains(&package_record.name),
+            PackageConfig::WithDeps { with_deps } => with_deps.0.matches(&package_record),
+            PackageConfig::MatchSpec(matchspec) => matchspec.0.matches(&package_record),
+        }
+    }
+
+    /// Whether the dependencies of packages matching this pattern should be mirrored as well.
+    pub(crate) fn with_deps(&self) -> bool {
+        matches!(self, PackageConfig::WithDeps { .. })
+    }
+}
+
+/// Formats the pattern the way it is written in the configuration file.
+impl std::fmt::Display for PackageConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageConfig::PackageGlob {
+                name_glob,
+                matchspec,
+                build_glob,
+            } => {
+                write!(f, "name-glob: {}", name_glob.0)?;
                 if let Some(matchspec) = matchspec {
-                    name_match && matchspec.0.matches(&package_record)
-                } else {
-                    name_match
+                    write!(f, ", matchspec: {}", matchspec.0)?;
+                }
+                if let Some(build_glob) = build_glob {
+                    write!(f, ", build-glob: {}", build_glob.0)?;
                 }
+                Ok(())
             }
-            PackageConfig::MatchSpec(matchspec) => matchspec.0.matches(&package_record),
+            PackageConfig::BuildStringContains { build_contains } => {
+                write!(f, "build-contains: {build_contains}")
+            }
+            PackageConfig::NameIn { names } => {
+                let mut names = names.iter().map(|n| n.as_source()).collect::<Vec<_>>();
+                names.sort();
+                write!(f, "names: [{}]", names.join(", "))
+            }
+            PackageConfig::WithDeps { with_deps } => write!(f, "with-deps: {}", with_deps.0),
+            PackageConfig::MatchSpec(matchspec) => write!(f, "{}", matchspec.0),
         }
     }
 }
@@ -194,6 +861,63 @@ pub struct S3Config {
     pub endpoint_url: Url,
     pub region: String,
     pub force_path_style: bool,
+    /// Server-side encryption of uploaded packages.
+    pub sse: Option<SseAlgorithm>,
+    /// The KMS key to use with [`SseAlgorithm::AwsKms`].
+    pub kms_key_id: Option<String>,
+    /// Storage class of uploaded objects, e.g. `INTELLIGENT_TIERING`. Defaults to `STANDARD`.
+    pub storage_class: Option<String>,
+    /// Checksum sent with uploaded objects so that S3 can verify their integrity.
+    pub checksum_algorithm: Option<S3ChecksumAlgorithm>,
+    /// ARN of an IAM role that is assumed to access the bucket.
+    pub role_arn: Option<String>,
+    /// The external ID used when assuming [`S3Config::role_arn`].
+    pub external_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SseAlgorithm {
+    #[serde(rename = "AES256")]
+    #[value(name = "AES256")]
+    Aes256,
+    #[serde(rename = "aws:kms")]
+    #[value(name = "aws:kms")]
+    AwsKms,
+}
+
+impl SseAlgorithm {
+    /// The value of the `x-amz-server-side-encryption` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SseAlgorithm::Aes256 => "AES256",
+            SseAlgorithm::AwsKms => "aws:kms",
+        }
+    }
+}
+
+/// Checksum that S3 verifies when packages are uploaded.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum S3ChecksumAlgorithm {
+    #[serde(rename = "CRC32C")]
+    #[value(name = "CRC32C")]
+    Crc32c,
+}
+
+impl S3ChecksumAlgorithm {
+    /// The name of the algorithm in the OpenDAL S3 configuration.
+    pub fn as_opendal_str(&self) -> &'static str {
+        match self {
+            S3ChecksumAlgorithm::Crc32c => "crc32c",
+        }
+    }
+}
+
+/// Package filters of a single subdir that replace the global `include` and `exclude`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SubdirConfig {
+    pub include: Option<Vec<PackageConfig>>,
+    pub exclude: Option<Vec<PackageConfig>>,
 }
 
 // TODO: allow setting it in .s3-config globally for both source and dest
@@ -208,12 +932,75 @@ pub struct S3ConfigSourceDest {
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct CondaMirrorYamlConfig {
     pub source: Option<NamedChannelOrUrl>,
+    pub sources: Option<Vec<NamedChannelOrUrl>>,
+    pub fallback_sources: Option<Vec<NamedChannelOrUrl>>,
+    pub conflict_resolution: Option<ConflictResolution>,
     pub destination: Option<NamedChannelOrUrl>,
+    pub destinations: Option<Vec<NamedChannelOrUrl>>,
     pub subdirs: Option<Vec<Platform>>,
+    pub include_subdirs: Option<Vec<GlobPattern>>,
+    pub exclude_subdirs: Option<Vec<GlobPattern>>,
+    pub subdir_aliases: Option<HashMap<Platform, Vec<String>>>,
 
     pub include: Option<Vec<PackageConfig>>,
     pub exclude: Option<Vec<PackageConfig>>,
+    pub intersection: Option<Vec<PackageConfig>>,
+    pub subdir_overrides: Option<HashMap<Platform, SubdirConfig>>,
     pub s3_config: Option<S3ConfigSourceDest>,
+    pub webdav: Option<bool>,
+    pub webdav_username: Option<String>,
+    pub webdav_password: Option<WebDavPassword>,
+    pub b2_application_key_id: Option<String>,
+    pub b2_application_key: Option<B2ApplicationKey>,
+    pub b2_bucket_id: Option<String>,
+    pub sftp_private_key_path: Option<PathBuf>,
+    pub sftp_host_key_check: Option<bool>,
+    pub write_jlap: Option<bool>,
+    pub write_checksums_separately: Option<bool>,
+    pub write_manifest: Option<bool>,
+    pub write_package_listing: Option<PathBuf>,
+    pub connect_timeout_secs: Option<u64>,
+    pub read_timeout_secs: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+    pub bytes_per_ms_timeout: Option<f64>,
+    pub source_max_redirects: Option<usize>,
+    pub use_http2: Option<bool>,
+    pub user_agent: Option<String>,
+    pub bandwidth_limit_bytes_per_sec: Option<u64>,
+    pub source_probe_timeout_ms: Option<u64>,
+    pub retry_delay_override_secs: Option<u64>,
+    pub retry_max_retries: Option<u32>,
+    pub retry_initial_interval_ms: Option<u64>,
+    pub retry_multiplier: Option<u32>,
+    pub enable_opendal_circuit_breaker: Option<bool>,
+    pub circuit_breaker_threshold: Option<usize>,
+    pub circuit_breaker_cooldown_secs: Option<u64>,
+    pub max_delete_fraction: Option<f64>,
+    pub soft_delete: Option<bool>,
+    pub trash_prefix: Option<String>,
+    pub smart_delete: Option<usize>,
+    pub preserve_removed: Option<bool>,
+    pub preload_repodata: Option<bool>,
+    pub metrics_port: Option<u16>,
+    pub webhook_url: Option<Url>,
+    pub webhook_on_failure_only: Option<bool>,
+    pub mirror_stats_db: Option<PathBuf>,
+    pub since: Option<DateTime<Utc>>,
+    pub channel_prune_age_days: Option<u32>,
+    pub with_deps: Option<bool>,
+    pub exclude_prerelease: Option<bool>,
+    pub max_packages: Option<usize>,
+    pub fail_on_missing_checksums: Option<bool>,
+    pub package_name_transform: Option<String>,
+    pub sign_packages: Option<bool>,
+    pub signing_key_path: Option<PathBuf>,
+    pub signing_key_id: Option<String>,
+    pub bearer_token: Option<BearerToken>,
+    pub quetz_token: Option<QuetzToken>,
+    pub quetz_token_location: Option<QuetzTokenLocation>,
+    pub conda_token: Option<CondaToken>,
+    pub source_display_name: Option<String>,
+    pub source_type: Option<SourceType>,
 }
 
 /* -------------------------------------------- CONFIG ------------------------------------------- */
@@ -229,31 +1016,311 @@ pub enum MirrorMode {
     /// Mirror all packages except those matching the given patterns.
     /// Override excludes with include patterns.
     IncludeExclude(Vec<PackageConfig>, Vec<PackageConfig>),
+    /// Mirror only packages matching all of the given patterns.
+    Intersection(Vec<PackageConfig>),
 }
 
-#[derive(Clone, Debug)]
-pub struct CondaMirrorConfig {
-    pub source: NamedChannelOrUrl,
-    pub destination: NamedChannelOrUrl,
-    pub subdirs: Option<Vec<Platform>>,
-    pub mode: MirrorMode,
-    pub s3_config_source: Option<S3Config>,
-    pub s3_config_destination: Option<S3Config>,
-    pub s3_credentials_source: Option<S3Credentials>,
+impl MirrorMode {
+    /// The patterns that select packages to mirror, as opposed to patterns that exclude them.
+    pub(crate) fn include_patterns(&self) -> &[PackageConfig] {
+        match self {
+            MirrorMode::All | MirrorMode::AllButExclude(_) => &[],
+            MirrorMode::OnlyInclude(include) | MirrorMode::IncludeExclude(include, _) => include,
+            MirrorMode::Intersection(patterns) => patterns,
+        }
+    }
+
+    /// The patterns that exclude packages from mirroring.
+    pub(crate) fn exclude_patterns(&self) -> &[PackageConfig] {
+        match self {
+            MirrorMode::AllButExclude(exclude) | MirrorMode::IncludeExclude(_, exclude) => exclude,
+            MirrorMode::All | MirrorMode::OnlyInclude(_) | MirrorMode::Intersection(_) => &[],
+        }
+    }
+}
+
+/// How to handle packages with the same filename but different content in multiple sources.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictResolution {
+    /// Keep the package from the source that is listed first.
+    #[default]
+    FirstWins,
+    /// Keep the package from the source that is listed last.
+    LastWins,
+    /// Abort mirroring.
+    Error,
+}
+
+/// Custom filter that is applied to the packages after filtering by [`MirrorMode`].
+///
+/// Only packages for which all filters return `true` are mirrored.
+pub trait PackageFilter: Send + Sync {
+    fn keep(&self, filename: &str, record: &PackageRecord) -> bool;
+}
+
+impl<F> PackageFilter for F
+where
+    F: Fn(&str, &PackageRecord) -> bool + Send + Sync,
+{
+    fn keep(&self, filename: &str, record: &PackageRecord) -> bool {
+        self(filename, record)
+    }
+}
+
+impl std::fmt::Debug for dyn PackageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PackageFilter")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CondaMirrorConfig {
+    pub source: NamedChannelOrUrl,
+    /// Sources that are mirrored in addition to `source`, in order.
+    pub additional_sources: Vec<NamedChannelOrUrl>,
+    /// Sources that are used instead of `source`, in order, if it can't be reached.
+    pub fallback_sources: Vec<NamedChannelOrUrl>,
+    pub conflict_resolution: ConflictResolution,
+    /// Layout of all sources.
+    pub source_type: SourceType,
+    pub destination: NamedChannelOrUrl,
+    /// Destinations that the packages are written to in addition to `destination`, in order.
+    pub additional_destinations: Vec<NamedChannelOrUrl>,
+    pub subdirs: Option<Vec<Platform>>,
+    /// Globs of which the mirrored subdirs have to match at least one, unless it is empty.
+    pub include_subdirs: Vec<GlobPattern>,
+    /// Globs of subdirs that are not mirrored.
+    pub exclude_subdirs: Vec<GlobPattern>,
+    /// Additional names under which a mirrored subdir is made available in the destination.
+    pub subdir_aliases: HashMap<Platform, Vec<String>>,
+    pub mode: MirrorMode,
+    /// Modes that replace `mode` for individual subdirs.
+    pub subdir_modes: HashMap<Platform, MirrorMode>,
+    pub s3_config_source: Option<S3Config>,
+    pub s3_config_destination: Option<S3Config>,
+    pub s3_credentials_source: Option<S3Credentials>,
     pub s3_credentials_destination: Option<S3Credentials>,
+    /// Whether the http(s) destination is a WebDAV server.
+    pub webdav: bool,
+    pub webdav_credentials: Option<WebDavCredentials>,
+    /// Credentials and bucket ID of `b2://` destinations.
+    pub b2_credentials: Option<B2Credentials>,
+    pub b2_bucket_id: Option<String>,
+    /// SSH private key used for SFTP destinations.
+    pub sftp_private_key_path: Option<PathBuf>,
+    /// Whether to verify the host key of SFTP destinations.
+    pub sftp_host_key_check: bool,
+    /// Append the changes to `repodata.json` to `repodata.jlap`.
+    pub write_jlap: bool,
+    /// Write a `<package>.sha256` file in `sha256sum` format next to each package.
+    pub write_sha256_sidecar: bool,
+    /// Write `manifest.json` with the checksums and size of all packages to each subdir.
+    pub write_manifest: bool,
+    /// File to write the paths of all mirrored packages to.
+    pub package_listing_path: Option<PathBuf>,
+    pub connect_timeout_secs: Option<u64>,
+    pub read_timeout_secs: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+    /// Minimum expected download speed in bytes per millisecond, used to scale the timeout of
+    /// package downloads with their size.
+    pub bytes_per_ms_timeout: Option<f64>,
+    /// Maximum number of redirects that are followed for requests to the source.
+    pub source_max_redirects: Option<usize>,
+    /// Force HTTP/2 (`Some(true)`) or HTTP/1.1 (`Some(false)`), negotiated using TLS if unset.
+    pub use_http2: Option<bool>,
+    /// User-Agent header of the HTTP client, `conda-mirror/<version>` if unset.
+    pub user_agent: Option<String>,
+    /// Limit of the combined download speed of all packages.
+    pub bandwidth_limit_bytes_per_sec: Option<u64>,
+    pub source_probe_timeout_ms: Option<u64>,
+    /// Fixed delay between retries that replaces the exponential backoff.
+    pub retry_delay_override_secs: Option<u64>,
+    /// Retries of failed requests to the source and operations on the destination.
+    pub retry_max_retries: Option<u32>,
+    pub retry_initial_interval_ms: Option<u64>,
+    pub retry_multiplier: Option<u32>,
+    /// Fail storage operations immediately for a while after repeated errors.
+    pub opendal_circuit_breaker: bool,
+    pub circuit_breaker_threshold: Option<usize>,
+    pub circuit_breaker_cooldown_secs: Option<u64>,
+    pub max_delete_fraction: Option<f64>,
+    pub force_delete: bool,
+    /// Move deleted packages to `{trash_prefix}{subdir}/{filename}_{timestamp}`.
+    pub soft_delete: bool,
+    pub trash_prefix: Option<String>,
+    /// Number of consecutive runs a package has to be missing in the source before it is deleted.
+    pub smart_delete_runs: Option<usize>,
+    /// Keep packages that were removed from the source in the destination and its repodata.
+    pub preserve_removed: bool,
+    /// Refuse to mirror packages without a SHA256 in the repodata.
+    pub fail_on_missing_checksums: bool,
+    /// Overwrite packages that already exist in the destination.
+    pub force_redownload: bool,
+    /// Only delete packages from the destination, without adding the missing ones.
+    pub delete_only: bool,
+    /// Print the package actions as tab-separated values to stdout.
+    pub output_tsv: bool,
+    /// Print the packages that were added or deleted in the run.
+    pub output_changed_packages_only: bool,
+    /// File to write the changed packages to instead of stdout.
+    pub changed_packages_path: Option<PathBuf>,
+    pub preload_repodata: bool,
+    /// Port to serve Prometheus metrics on while mirroring.
+    pub metrics_port: Option<u16>,
+    /// URL that the outcome of the run is POSTed to.
+    pub webhook_url: Option<Url>,
+    pub webhook_on_failure_only: bool,
+    /// SQLite database to record the statistics of the run in.
+    pub stats_db_path: Option<PathBuf>,
+    /// Only mirror packages with a newer timestamp.
+    pub since: Option<DateTime<Utc>>,
+    /// Don't mirror packages older than this many days and delete them from the destination.
+    pub channel_prune_age_days: Option<u32>,
+    /// Also mirror the dependencies of all selected packages.
+    pub with_deps: bool,
+    /// Don't mirror pre-release versions.
+    pub exclude_prerelease: bool,
+    /// Maximum number of packages mirrored per subdir, taking the first ones by filename.
+    pub max_packages: Option<usize>,
+    /// Shell command that maps package filenames to the filenames in the destination.
+    pub package_name_transform_command: Option<String>,
+    /// Don't draw progress bars.
+    pub hide_progress: bool,
+    /// Don't draw progress bars or print status messages.
+    pub quiet: bool,
+    pub resume: bool,
+    pub checkpoint_path: Option<PathBuf>,
+    pub sign_packages: bool,
+    pub signing_key_path: Option<PathBuf>,
+    pub signing_key_id: Option<String>,
+    pub bearer_token: Option<BearerToken>,
+    pub quetz_token: Option<QuetzToken>,
+    pub quetz_token_location: QuetzTokenLocation,
+    pub conda_token: Option<CondaToken>,
+    pub source_display_name: Option<String>,
+    pub custom_filters: Vec<Arc<dyn PackageFilter>>,
 }
 
 impl CondaMirrorConfig {
-    fn platform_url(&self, platform: Platform) -> miette::Result<Url> {
-        let channel = self
-            .source
+    /// All sources in the order in which they are mirrored.
+    pub fn sources(&self) -> impl Iterator<Item = &NamedChannelOrUrl> {
+        std::iter::once(&self.source).chain(self.additional_sources.iter())
+    }
+
+    /// All destinations in the order in which they were configured.
+    pub fn destinations(&self) -> impl Iterator<Item = &NamedChannelOrUrl> {
+        std::iter::once(&self.destination).chain(self.additional_destinations.iter())
+    }
+
+    /// Whether `subdir` passes `include_subdirs` and `exclude_subdirs`.
+    pub fn subdir_selected(&self, subdir: Platform) -> bool {
+        let matches = |pattern: &GlobPattern| pattern.0.matches(subdir.as_str());
+        (self.include_subdirs.is_empty() || self.include_subdirs.iter().any(matches))
+            && !self.exclude_subdirs.iter().any(matches)
+    }
+
+    /// The mode used to select the packages of `subdir`.
+    pub fn mode_for(&self, subdir: Platform) -> &MirrorMode {
+        self.subdir_modes.get(&subdir).unwrap_or(&self.mode)
+    }
+
+    /// Returns a copy of this configuration that mirrors only `source`.
+    ///
+    /// The fallback sources are only kept for the first source.
+    pub(crate) fn for_source(&self, source: &NamedChannelOrUrl) -> CondaMirrorConfig {
+        let fallback_sources = if source == &self.source {
+            self.fallback_sources.clone()
+        } else {
+            Vec::new()
+        };
+        CondaMirrorConfig {
+            source: source.clone(),
+            additional_sources: Vec::new(),
+            fallback_sources,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this configuration that writes only to `destination`.
+    pub(crate) fn for_destination(&self, destination: &NamedChannelOrUrl) -> CondaMirrorConfig {
+        CondaMirrorConfig {
+            destination: destination.clone(),
+            additional_destinations: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Name of the source channel in user-facing output.
+    ///
+    /// Falls back to the last path component of the source, e.g. `conda-forge` for
+    /// `https://conda.anaconda.org/conda-forge/`.
+    pub fn source_display_name(&self) -> String {
+        if let Some(name) = &self.source_display_name {
+            return name.clone();
+        }
+        let last_component = match &self.source {
+            NamedChannelOrUrl::Name(name) => Some(name.clone()),
+            NamedChannelOrUrl::Url(url) => url
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+                .map(str::to_string)
+                .or_else(|| url.host_str().map(str::to_string)),
+            NamedChannelOrUrl::Path(path) => path
+                .as_str()
+                .trim_end_matches(['/', '\\'])
+                .rsplit(['/', '\\'])
+                .next()
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+        };
+        last_component.unwrap_or_else(|| self.source.to_string())
+    }
+
+    fn source_channel(&self) -> miette::Result<Channel> {
+        self.source
             .clone()
             .into_channel(&ChannelConfig::default_with_root_dir(
                 current_dir().into_diagnostic()?,
             ))
-            .into_diagnostic()?;
+            .into_diagnostic()
+    }
 
-        Ok(channel.platform_url(platform))
+    /// Packages built before this point in time are pruned, see `channel_prune_age_days`.
+    pub(crate) fn prune_before(&self) -> Option<DateTime<Utc>> {
+        self.channel_prune_age_days
+            .map(|days| Utc::now() - chrono::Duration::days(days.into()))
+    }
+
+    /// The directory of the source, if it is local.
+    pub(crate) fn local_source_path(&self) -> miette::Result<Option<PathBuf>> {
+        let source_url = self.source_channel()?.base_url.url().clone();
+        if source_url.scheme() != "file" {
+            return Ok(None);
+        }
+        source_url
+            .to_file_path()
+            .map(Some)
+            .map_err(|_| miette::miette!("Invalid file path: {}", source_url))
+    }
+
+    /// Returns an error if the source is a local directory that does not exist.
+    pub(crate) fn check_local_source(&self) -> miette::Result<()> {
+        let Some(path) = self.local_source_path()? else {
+            return Ok(());
+        };
+        if !path.is_dir() {
+            return Err(miette::miette!(
+                help = "Check the path of the source, local channels must already exist",
+                "Source channel directory {} does not exist",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    fn platform_url(&self, platform: Platform) -> miette::Result<Url> {
+        Ok(self.source_channel()?.platform_url(platform))
     }
 
     pub(crate) fn repodata_url(&self, platform: Platform) -> miette::Result<Url> {
@@ -265,10 +1332,863 @@ impl CondaMirrorConfig {
     }
 
     pub(crate) fn package_url(&self, filename: &str, platform: Platform) -> miette::Result<Url> {
-        let package_url = self
-            .platform_url(platform)?
-            .join(filename)
-            .into_diagnostic()?;
-        Ok(package_url)
+        let base_url = match self.source_type {
+            SourceType::Channel => self.platform_url(platform)?,
+            // The packages of all subdirs are in the same directory.
+            SourceType::PackageCache => Url::clone(self.source_channel()?.base_url.url()),
+        };
+        base_url.join(filename).into_diagnostic()
+    }
+}
+
+/// Builder for [`CondaMirrorConfig`].
+///
+/// ```no_run
+/// use conda_mirror::config::CondaMirrorConfig;
+///
+/// let config = CondaMirrorConfig::builder()
+///     .source("conda-forge".parse().unwrap())
+///     .destination("./my-channel".parse().unwrap())
+///     .subdirs(vec!["noarch".parse().unwrap()])
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CondaMirrorConfigBuilder {
+    source: Option<NamedChannelOrUrl>,
+    additional_sources: Vec<NamedChannelOrUrl>,
+    fallback_sources: Vec<NamedChannelOrUrl>,
+    conflict_resolution: ConflictResolution,
+    source_type: SourceType,
+    destination: Option<NamedChannelOrUrl>,
+    additional_destinations: Vec<NamedChannelOrUrl>,
+    subdirs: Option<Vec<Platform>>,
+    include_subdirs: Vec<GlobPattern>,
+    exclude_subdirs: Vec<GlobPattern>,
+    subdir_aliases: HashMap<Platform, Vec<String>>,
+    mode: Option<MirrorMode>,
+    subdir_modes: HashMap<Platform, MirrorMode>,
+    s3_config_source: Option<S3Config>,
+    s3_config_destination: Option<S3Config>,
+    s3_credentials_source: Option<S3Credentials>,
+    s3_credentials_destination: Option<S3Credentials>,
+    webdav: bool,
+    webdav_credentials: Option<WebDavCredentials>,
+    b2_credentials: Option<B2Credentials>,
+    b2_bucket_id: Option<String>,
+    sftp_private_key_path: Option<PathBuf>,
+    sftp_host_key_check: Option<bool>,
+    write_jlap: bool,
+    write_sha256_sidecar: bool,
+    write_manifest: bool,
+    package_listing_path: Option<PathBuf>,
+    connect_timeout_secs: Option<u64>,
+    read_timeout_secs: Option<u64>,
+    request_timeout_secs: Option<u64>,
+    bytes_per_ms_timeout: Option<f64>,
+    source_max_redirects: Option<usize>,
+    use_http2: Option<bool>,
+    user_agent: Option<String>,
+    bandwidth_limit_bytes_per_sec: Option<u64>,
+    source_probe_timeout_ms: Option<u64>,
+    retry_delay_override_secs: Option<u64>,
+    retry_max_retries: Option<u32>,
+    retry_initial_interval_ms: Option<u64>,
+    retry_multiplier: Option<u32>,
+    opendal_circuit_breaker: bool,
+    circuit_breaker_threshold: Option<usize>,
+    circuit_breaker_cooldown_secs: Option<u64>,
+    max_delete_fraction: Option<f64>,
+    force_delete: bool,
+    soft_delete: bool,
+    trash_prefix: Option<String>,
+    smart_delete_runs: Option<usize>,
+    preserve_removed: bool,
+    fail_on_missing_checksums: bool,
+    force_redownload: bool,
+    delete_only: bool,
+    output_tsv: bool,
+    output_changed_packages_only: bool,
+    changed_packages_path: Option<PathBuf>,
+    preload_repodata: bool,
+    metrics_port: Option<u16>,
+    webhook_url: Option<Url>,
+    webhook_on_failure_only: bool,
+    stats_db_path: Option<PathBuf>,
+    since: Option<DateTime<Utc>>,
+    channel_prune_age_days: Option<u32>,
+    with_deps: bool,
+    exclude_prerelease: bool,
+    max_packages: Option<usize>,
+    package_name_transform_command: Option<String>,
+    hide_progress: bool,
+    quiet: bool,
+    resume: bool,
+    checkpoint_path: Option<PathBuf>,
+    sign_packages: bool,
+    signing_key_path: Option<PathBuf>,
+    signing_key_id: Option<String>,
+    bearer_token: Option<BearerToken>,
+    quetz_token: Option<QuetzToken>,
+    quetz_token_location: QuetzTokenLocation,
+    conda_token: Option<CondaToken>,
+    source_display_name: Option<String>,
+    custom_filters: Vec<Arc<dyn PackageFilter>>,
+}
+
+impl CondaMirrorConfig {
+    pub fn builder() -> CondaMirrorConfigBuilder {
+        CondaMirrorConfigBuilder::default()
+    }
+}
+
+impl CondaMirrorConfigBuilder {
+    pub fn source(&mut self, source: NamedChannelOrUrl) -> &mut Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the sources to mirror. The first one is used as `source`, packages of the others are
+    /// merged into the destination in order.
+    pub fn sources(&mut self, sources: Vec<NamedChannelOrUrl>) -> &mut Self {
+        let mut sources = sources.into_iter();
+        self.source = sources.next();
+        self.additional_sources = sources.collect();
+        self
+    }
+
+    /// Sets the sources that are tried in order if the first source can't be reached.
+    pub fn fallback_sources(&mut self, fallback_sources: Vec<NamedChannelOrUrl>) -> &mut Self {
+        self.fallback_sources = fallback_sources;
+        self
+    }
+
+    pub fn conflict_resolution(&mut self, conflict_resolution: ConflictResolution) -> &mut Self {
+        self.conflict_resolution = conflict_resolution;
+        self
+    }
+
+    pub fn source_type(&mut self, source_type: SourceType) -> &mut Self {
+        self.source_type = source_type;
+        self
+    }
+
+    pub fn destination(&mut self, destination: NamedChannelOrUrl) -> &mut Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Sets the destinations to mirror to. The first one is used as `destination`, the packages
+    /// are written to all of them.
+    pub fn destinations(&mut self, destinations: Vec<NamedChannelOrUrl>) -> &mut Self {
+        let mut destinations = destinations.into_iter();
+        self.destination = destinations.next();
+        self.additional_destinations = destinations.collect();
+        self
+    }
+
+    /// The subdirs to mirror. If unset, all subdirs of the source are mirrored.
+    pub fn subdirs(&mut self, subdirs: impl Into<Option<Vec<Platform>>>) -> &mut Self {
+        self.subdirs = subdirs.into();
+        self
+    }
+
+    /// Only subdirs matching one of the globs are mirrored, unless there are none.
+    pub fn include_subdirs(&mut self, include_subdirs: Vec<GlobPattern>) -> &mut Self {
+        self.include_subdirs = include_subdirs;
+        self
+    }
+
+    /// Subdirs matching one of the globs are not mirrored.
+    pub fn exclude_subdirs(&mut self, exclude_subdirs: Vec<GlobPattern>) -> &mut Self {
+        self.exclude_subdirs = exclude_subdirs;
+        self
+    }
+
+    /// Makes a mirrored subdir additionally available under the given names, e.g. `linux-64` as
+    /// `linux-amd64`.
+    pub fn subdir_aliases(&mut self, subdir_aliases: HashMap<Platform, Vec<String>>) -> &mut Self {
+        self.subdir_aliases = subdir_aliases;
+        self
+    }
+
+    /// Defaults to [`MirrorMode::All`].
+    pub fn mode(&mut self, mode: MirrorMode) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Modes that are used instead of the global mode for individual subdirs.
+    pub fn subdir_modes(&mut self, subdir_modes: HashMap<Platform, MirrorMode>) -> &mut Self {
+        self.subdir_modes = subdir_modes;
+        self
+    }
+
+    pub fn s3_config_source(&mut self, s3_config: impl Into<Option<S3Config>>) -> &mut Self {
+        self.s3_config_source = s3_config.into();
+        self
+    }
+
+    pub fn s3_config_destination(&mut self, s3_config: impl Into<Option<S3Config>>) -> &mut Self {
+        self.s3_config_destination = s3_config.into();
+        self
+    }
+
+    pub fn s3_credentials_source(
+        &mut self,
+        s3_credentials: impl Into<Option<S3Credentials>>,
+    ) -> &mut Self {
+        self.s3_credentials_source = s3_credentials.into();
+        self
+    }
+
+    pub fn s3_credentials_destination(
+        &mut self,
+        s3_credentials: impl Into<Option<S3Credentials>>,
+    ) -> &mut Self {
+        self.s3_credentials_destination = s3_credentials.into();
+        self
+    }
+
+    pub fn webdav(&mut self, webdav: bool) -> &mut Self {
+        self.webdav = webdav;
+        self
+    }
+
+    pub fn webdav_credentials(
+        &mut self,
+        webdav_credentials: impl Into<Option<WebDavCredentials>>,
+    ) -> &mut Self {
+        self.webdav_credentials = webdav_credentials.into();
+        self
+    }
+
+    pub fn b2_credentials(
+        &mut self,
+        b2_credentials: impl Into<Option<B2Credentials>>,
+    ) -> &mut Self {
+        self.b2_credentials = b2_credentials.into();
+        self
+    }
+
+    pub fn b2_bucket_id(&mut self, bucket_id: impl Into<Option<String>>) -> &mut Self {
+        self.b2_bucket_id = bucket_id.into();
+        self
+    }
+
+    pub fn sftp_private_key_path(&mut self, path: impl Into<Option<PathBuf>>) -> &mut Self {
+        self.sftp_private_key_path = path.into();
+        self
+    }
+
+    /// Whether to verify the host key of SFTP destinations, enabled by default.
+    pub fn sftp_host_key_check(&mut self, sftp_host_key_check: bool) -> &mut Self {
+        self.sftp_host_key_check = Some(sftp_host_key_check);
+        self
+    }
+
+    pub fn write_jlap(&mut self, write_jlap: bool) -> &mut Self {
+        self.write_jlap = write_jlap;
+        self
+    }
+
+    pub fn write_sha256_sidecar(&mut self, write_sha256_sidecar: bool) -> &mut Self {
+        self.write_sha256_sidecar = write_sha256_sidecar;
+        self
+    }
+
+    pub fn write_manifest(&mut self, write_manifest: bool) -> &mut Self {
+        self.write_manifest = write_manifest;
+        self
+    }
+
+    pub fn package_listing_path(&mut self, path: impl Into<Option<PathBuf>>) -> &mut Self {
+        self.package_listing_path = path.into();
+        self
+    }
+
+    pub fn connect_timeout_secs(&mut self, secs: impl Into<Option<u64>>) -> &mut Self {
+        self.connect_timeout_secs = secs.into();
+        self
+    }
+
+    pub fn read_timeout_secs(&mut self, secs: impl Into<Option<u64>>) -> &mut Self {
+        self.read_timeout_secs = secs.into();
+        self
+    }
+
+    pub fn request_timeout_secs(&mut self, secs: impl Into<Option<u64>>) -> &mut Self {
+        self.request_timeout_secs = secs.into();
+        self
+    }
+
+    pub fn bytes_per_ms_timeout(&mut self, bytes_per_ms: impl Into<Option<f64>>) -> &mut Self {
+        self.bytes_per_ms_timeout = bytes_per_ms.into();
+        self
+    }
+
+    pub fn source_max_redirects(&mut self, max_redirects: impl Into<Option<usize>>) -> &mut Self {
+        self.source_max_redirects = max_redirects.into();
+        self
+    }
+
+    pub fn use_http2(&mut self, use_http2: impl Into<Option<bool>>) -> &mut Self {
+        self.use_http2 = use_http2.into();
+        self
+    }
+
+    pub fn user_agent(&mut self, user_agent: impl Into<Option<String>>) -> &mut Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn bandwidth_limit_bytes_per_sec(
+        &mut self,
+        bytes_per_sec: impl Into<Option<u64>>,
+    ) -> &mut Self {
+        self.bandwidth_limit_bytes_per_sec = bytes_per_sec.into();
+        self
+    }
+
+    pub fn source_probe_timeout_ms(&mut self, ms: impl Into<Option<u64>>) -> &mut Self {
+        self.source_probe_timeout_ms = ms.into();
+        self
+    }
+
+    pub fn retry_delay_override_secs(&mut self, secs: impl Into<Option<u64>>) -> &mut Self {
+        self.retry_delay_override_secs = secs.into();
+        self
     }
+
+    pub fn retry_max_retries(&mut self, max_retries: impl Into<Option<u32>>) -> &mut Self {
+        self.retry_max_retries = max_retries.into();
+        self
+    }
+
+    pub fn retry_initial_interval_ms(&mut self, ms: impl Into<Option<u64>>) -> &mut Self {
+        self.retry_initial_interval_ms = ms.into();
+        self
+    }
+
+    pub fn retry_multiplier(&mut self, multiplier: impl Into<Option<u32>>) -> &mut Self {
+        self.retry_multiplier = multiplier.into();
+        self
+    }
+
+    pub fn opendal_circuit_breaker(&mut self, enabled: bool) -> &mut Self {
+        self.opendal_circuit_breaker = enabled;
+        self
+    }
+
+    pub fn circuit_breaker_threshold(&mut self, threshold: impl Into<Option<usize>>) -> &mut Self {
+        self.circuit_breaker_threshold = threshold.into();
+        self
+    }
+
+    pub fn circuit_breaker_cooldown_secs(&mut self, secs: impl Into<Option<u64>>) -> &mut Self {
+        self.circuit_breaker_cooldown_secs = secs.into();
+        self
+    }
+
+    pub fn max_delete_fraction(&mut self, fraction: impl Into<Option<f64>>) -> &mut Self {
+        self.max_delete_fraction = fraction.into();
+        self
+    }
+
+    pub fn force_delete(&mut self, force_delete: bool) -> &mut Self {
+        self.force_delete = force_delete;
+        self
+    }
+
+    /// Move deleted packages to the trash prefix instead of removing them.
+    pub fn soft_delete(&mut self, soft_delete: bool) -> &mut Self {
+        self.soft_delete = soft_delete;
+        self
+    }
+
+    pub fn trash_prefix(&mut self, prefix: impl Into<Option<String>>) -> &mut Self {
+        self.trash_prefix = prefix.into();
+        self
+    }
+
+    /// Keep packages that are missing in the source until they were missing for `runs`
+    /// consecutive runs. Requires `stats_db_path`.
+    pub fn smart_delete_runs(&mut self, runs: impl Into<Option<usize>>) -> &mut Self {
+        self.smart_delete_runs = runs.into();
+        self
+    }
+
+    /// Keep packages that were removed from the source in the destination, the repodata of the
+    /// destination only ever grows.
+    pub fn preserve_removed(&mut self, preserve_removed: bool) -> &mut Self {
+        self.preserve_removed = preserve_removed;
+        self
+    }
+
+    pub fn fail_on_missing_checksums(&mut self, fail: bool) -> &mut Self {
+        self.fail_on_missing_checksums = fail;
+        self
+    }
+
+    pub fn force_redownload(&mut self, force_redownload: bool) -> &mut Self {
+        self.force_redownload = force_redownload;
+        self
+    }
+
+    /// Only delete packages that are not selected anymore, for cleaning up a destination without
+    /// downloading anything.
+    pub fn delete_only(&mut self, delete_only: bool) -> &mut Self {
+        self.delete_only = delete_only;
+        self
+    }
+
+    pub fn output_tsv(&mut self, output_tsv: bool) -> &mut Self {
+        self.output_tsv = output_tsv;
+        self
+    }
+
+    pub fn output_changed_packages_only(
+        &mut self,
+        output_changed_packages_only: bool,
+    ) -> &mut Self {
+        self.output_changed_packages_only = output_changed_packages_only;
+        self
+    }
+
+    pub fn changed_packages_path(&mut self, path: impl Into<Option<PathBuf>>) -> &mut Self {
+        self.changed_packages_path = path.into();
+        self
+    }
+
+    pub fn preload_repodata(&mut self, preload_repodata: bool) -> &mut Self {
+        self.preload_repodata = preload_repodata;
+        self
+    }
+
+    pub fn metrics_port(&mut self, port: impl Into<Option<u16>>) -> &mut Self {
+        self.metrics_port = port.into();
+        self
+    }
+
+    pub fn webhook_url(&mut self, url: impl Into<Option<Url>>) -> &mut Self {
+        self.webhook_url = url.into();
+        self
+    }
+
+    /// Only call the webhook for failed runs.
+    pub fn webhook_on_failure_only(&mut self, on_failure_only: bool) -> &mut Self {
+        self.webhook_on_failure_only = on_failure_only;
+        self
+    }
+
+    pub fn stats_db_path(&mut self, path: impl Into<Option<PathBuf>>) -> &mut Self {
+        self.stats_db_path = path.into();
+        self
+    }
+
+    /// Only mirror packages built after `since`. Older packages in the destination are kept.
+    pub fn since(&mut self, since: impl Into<Option<DateTime<Utc>>>) -> &mut Self {
+        self.since = since.into();
+        self
+    }
+
+    pub fn channel_prune_age_days(&mut self, days: impl Into<Option<u32>>) -> &mut Self {
+        self.channel_prune_age_days = days.into();
+        self
+    }
+
+    pub fn with_deps(&mut self, with_deps: bool) -> &mut Self {
+        self.with_deps = with_deps;
+        self
+    }
+
+    pub fn exclude_prerelease(&mut self, exclude_prerelease: bool) -> &mut Self {
+        self.exclude_prerelease = exclude_prerelease;
+        self
+    }
+
+    pub fn max_packages(&mut self, max_packages: impl Into<Option<usize>>) -> &mut Self {
+        self.max_packages = max_packages.into();
+        self
+    }
+
+    pub fn package_name_transform_command(
+        &mut self,
+        command: impl Into<Option<String>>,
+    ) -> &mut Self {
+        self.package_name_transform_command = command.into();
+        self
+    }
+
+    pub fn hide_progress(&mut self, hide_progress: bool) -> &mut Self {
+        self.hide_progress = hide_progress;
+        self
+    }
+
+    pub fn quiet(&mut self, quiet: bool) -> &mut Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn resume(&mut self, resume: bool) -> &mut Self {
+        self.resume = resume;
+        self
+    }
+
+    pub fn checkpoint_path(&mut self, path: impl Into<Option<PathBuf>>) -> &mut Self {
+        self.checkpoint_path = path.into();
+        self
+    }
+
+    pub fn sign_packages(&mut self, sign_packages: bool) -> &mut Self {
+        self.sign_packages = sign_packages;
+        self
+    }
+
+    pub fn signing_key_path(&mut self, path: impl Into<Option<PathBuf>>) -> &mut Self {
+        self.signing_key_path = path.into();
+        self
+    }
+
+    pub fn signing_key_id(&mut self, key_id: impl Into<Option<String>>) -> &mut Self {
+        self.signing_key_id = key_id.into();
+        self
+    }
+
+    pub fn bearer_token(&mut self, token: impl Into<Option<String>>) -> &mut Self {
+        self.bearer_token = token.into().map(BearerToken);
+        self
+    }
+
+    pub fn quetz_token(&mut self, token: impl Into<Option<String>>) -> &mut Self {
+        self.quetz_token = token.into().map(QuetzToken);
+        self
+    }
+
+    pub fn quetz_token_location(&mut self, location: QuetzTokenLocation) -> &mut Self {
+        self.quetz_token_location = location;
+        self
+    }
+
+    /// Token that is appended to the URLs of all requests to anaconda.org and conda.io.
+    pub fn conda_token(&mut self, token: impl Into<Option<String>>) -> &mut Self {
+        self.conda_token = token.into().map(CondaToken);
+        self
+    }
+
+    /// Short name of the source used in user-facing output. If unset, it's derived from the source.
+    pub fn source_display_name(&mut self, name: impl Into<Option<String>>) -> &mut Self {
+        self.source_display_name = name.into();
+        self
+    }
+
+    /// Adds a custom filter, see [`PackageFilter`].
+    pub fn custom_filter(&mut self, filter: Arc<dyn PackageFilter>) -> &mut Self {
+        self.custom_filters.push(filter);
+        self
+    }
+
+    /// Validates the configuration and builds a [`CondaMirrorConfig`].
+    pub fn build(&self) -> miette::Result<CondaMirrorConfig> {
+        let (source, destination) = match (self.source.clone(), self.destination.clone()) {
+            (Some(source), Some(destination)) => (source, destination),
+            _ => return Err(miette::miette!("Source and target must be specified")),
+        };
+
+        if self.source_type == SourceType::PackageCache
+            && let Some(source) = std::iter::once(&source)
+                .chain(&self.additional_sources)
+                .chain(&self.fallback_sources)
+                .find(|source| !is_local_channel(source))
+        {
+            return Err(miette::miette!(
+                "Package caches can only be read from local directories, got {}",
+                source
+            ));
+        }
+        if is_s3_channel(&source) && self.s3_config_source.is_none() {
+            return Err(miette::miette!(
+                help = "set the S3 endpoint URL, region and addressing style of the source",
+                "No S3 source config set"
+            ));
+        }
+        let destinations =
+            || std::iter::once(&destination).chain(self.additional_destinations.iter());
+        if destinations().any(is_s3_channel) && self.s3_config_destination.is_none() {
+            return Err(miette::miette!(
+                help = "set the S3 endpoint URL, region and addressing style of the destination",
+                "No S3 destination config set"
+            ));
+        }
+        for s3_config in [&self.s3_config_source, &self.s3_config_destination]
+            .into_iter()
+            .flatten()
+        {
+            if s3_config.region.is_empty() {
+                return Err(miette::miette!("S3 region must not be empty"));
+            }
+            match (s3_config.sse, &s3_config.kms_key_id) {
+                (Some(SseAlgorithm::AwsKms), None) => {
+                    return Err(miette::miette!(
+                        "A KMS key ID is required for server-side encryption with aws:kms"
+                    ));
+                }
+                (Some(SseAlgorithm::Aes256) | None, Some(_)) => {
+                    return Err(miette::miette!(
+                        "A KMS key ID can only be used with server-side encryption aws:kms"
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if let Some(s3_config) = &self.s3_config_source
+            && s3_config.role_arn.is_some()
+        {
+            return Err(miette::miette!(
+                help =
+                    "provide credentials for the source using the rattler authentication storage",
+                "Assuming an IAM role is only supported for S3 destinations"
+            ));
+        }
+        if let Some(s3_config) = &self.s3_config_destination
+            && s3_config.external_id.is_some()
+            && s3_config.role_arn.is_none()
+        {
+            return Err(miette::miette!(
+                "An external ID for S3 is set but no role ARN"
+            ));
+        }
+        if self.s3_credentials_source.is_some() && !is_s3_channel(&source) {
+            return Err(miette::miette!("Source is not an S3 URL: {}", source));
+        }
+        if self.s3_credentials_destination.is_some() && !destinations().any(is_s3_channel) {
+            return Err(miette::miette!(
+                "Destination is not an S3 URL: {}",
+                destination
+            ));
+        }
+
+        if self.webdav && !destinations().any(is_http_channel) {
+            return Err(miette::miette!(
+                "WebDAV destinations must be http(s) URLs: {}",
+                destination
+            ));
+        }
+        if self.webdav_credentials.is_some() && !self.webdav {
+            return Err(miette::miette!(
+                help = "pass --webdav to upload to the destination using WebDAV",
+                "WebDAV credentials are set but the destination is not a WebDAV server"
+            ));
+        }
+
+        if destinations().any(is_b2_channel) {
+            if self.b2_credentials.is_none() {
+                return Err(miette::miette!(
+                    help = "pass --b2-application-key-id and --b2-application-key or set B2_APPLICATION_KEY_ID and B2_APPLICATION_KEY",
+                    "Missing B2 credentials"
+                ));
+            }
+            if self.b2_bucket_id.is_none() {
+                return Err(miette::miette!(
+                    help =
+                        "pass --b2-bucket-id, it is shown next to the bucket name in the B2 web UI",
+                    "Missing B2 bucket ID"
+                ));
+            }
+        } else if self.b2_bucket_id.is_some() {
+            return Err(miette::miette!(
+                "Destination is not a B2 URL: {}",
+                destination
+            ));
+        }
+
+        if self.quetz_token.is_some()
+            && !matches!(source, NamedChannelOrUrl::Name(_))
+            && !is_http_channel(&source)
+        {
+            return Err(miette::miette!(
+                "Quetz tokens can only be used with HTTP(S) sources, got {}",
+                source
+            ));
+        }
+
+        for alias in self.subdir_aliases.values().flatten() {
+            if alias.is_empty() || alias.contains('/') || alias == "." || alias == ".." {
+                return Err(miette::miette!("Invalid subdir alias: {:?}", alias));
+            }
+            if Platform::from_str(alias).is_ok() {
+                return Err(miette::miette!(
+                    "Subdir alias {} is the name of a subdir and would conflict with it",
+                    alias
+                ));
+            }
+        }
+
+        if let Some(bytes_per_ms) = self.bytes_per_ms_timeout
+            && !(bytes_per_ms.is_finite() && bytes_per_ms > 0.0)
+        {
+            return Err(miette::miette!(
+                "The download speed for timeouts must be a positive number, got {}",
+                bytes_per_ms
+            ));
+        }
+
+        if self.bandwidth_limit_bytes_per_sec == Some(0) {
+            return Err(miette::miette!(
+                "The bandwidth limit must be at least 1 byte per second"
+            ));
+        }
+        if self.retry_multiplier == Some(0) {
+            return Err(miette::miette!("The retry multiplier must be at least 1"));
+        }
+        if self.circuit_breaker_threshold == Some(0) {
+            return Err(miette::miette!(
+                "The circuit breaker threshold must be at least 1"
+            ));
+        }
+
+        if let Some(max_delete_fraction) = self.max_delete_fraction
+            && !(0.0..=1.0).contains(&max_delete_fraction)
+        {
+            return Err(miette::miette!(
+                "Maximum delete fraction must be between 0 and 1, got {}",
+                max_delete_fraction
+            ));
+        }
+
+        if let Some(runs) = self.smart_delete_runs {
+            if runs == 0 {
+                return Err(miette::miette!("Smart delete requires at least 1 run"));
+            }
+            if self.stats_db_path.is_none() {
+                return Err(miette::miette!(
+                    help = "pass --mirror-stats-db to keep track of missing packages",
+                    "Smart delete requires a stats database"
+                ));
+            }
+        }
+
+        if self.delete_only && self.force_redownload {
+            return Err(miette::miette!(
+                "--delete-removed-only cannot be combined with --force-redownload"
+            ));
+        }
+
+        if self.sign_packages && (self.signing_key_path.is_none() || self.signing_key_id.is_none())
+        {
+            return Err(miette::miette!(
+                help = "pass --signing-key-path and --signing-key-id",
+                "Signing packages requires a signing key path and key id"
+            ));
+        }
+
+        if self.additional_sources.iter().any(is_s3_channel) {
+            return Err(miette::miette!("Only the first source can be an S3 bucket"));
+        }
+        if self.fallback_sources.iter().any(is_s3_channel) {
+            return Err(miette::miette!("Fallback sources can't be S3 buckets"));
+        }
+
+        Ok(CondaMirrorConfig {
+            source,
+            additional_sources: self.additional_sources.clone(),
+            fallback_sources: self.fallback_sources.clone(),
+            conflict_resolution: self.conflict_resolution,
+            source_type: self.source_type,
+            destination,
+            additional_destinations: self.additional_destinations.clone(),
+            subdirs: self.subdirs.clone(),
+            include_subdirs: self.include_subdirs.clone(),
+            exclude_subdirs: self.exclude_subdirs.clone(),
+            subdir_aliases: self.subdir_aliases.clone(),
+            mode: self.mode.clone().unwrap_or(MirrorMode::All),
+            subdir_modes: self.subdir_modes.clone(),
+            s3_config_source: self.s3_config_source.clone(),
+            s3_config_destination: self.s3_config_destination.clone(),
+            s3_credentials_source: self.s3_credentials_source.clone(),
+            s3_credentials_destination: self.s3_credentials_destination.clone(),
+            webdav: self.webdav,
+            webdav_credentials: self.webdav_credentials.clone(),
+            b2_credentials: self.b2_credentials.clone(),
+            b2_bucket_id: self.b2_bucket_id.clone(),
+            sftp_private_key_path: self.sftp_private_key_path.clone(),
+            sftp_host_key_check: self.sftp_host_key_check.unwrap_or(true),
+            write_jlap: self.write_jlap,
+            write_sha256_sidecar: self.write_sha256_sidecar,
+            write_manifest: self.write_manifest,
+            package_listing_path: self.package_listing_path.clone(),
+            connect_timeout_secs: self.connect_timeout_secs,
+            read_timeout_secs: self.read_timeout_secs,
+            request_timeout_secs: self.request_timeout_secs,
+            bytes_per_ms_timeout: self.bytes_per_ms_timeout,
+            source_max_redirects: self.source_max_redirects,
+            use_http2: self.use_http2,
+            user_agent: self.user_agent.clone(),
+            bandwidth_limit_bytes_per_sec: self.bandwidth_limit_bytes_per_sec,
+            source_probe_timeout_ms: self.source_probe_timeout_ms,
+            retry_delay_override_secs: self.retry_delay_override_secs,
+            retry_max_retries: self.retry_max_retries,
+            retry_initial_interval_ms: self.retry_initial_interval_ms,
+            retry_multiplier: self.retry_multiplier,
+            opendal_circuit_breaker: self.opendal_circuit_breaker,
+            circuit_breaker_threshold: self.circuit_breaker_threshold,
+            circuit_breaker_cooldown_secs: self.circuit_breaker_cooldown_secs,
+            max_delete_fraction: self.max_delete_fraction,
+            force_delete: self.force_delete,
+            soft_delete: self.soft_delete,
+            trash_prefix: self.trash_prefix.clone(),
+            smart_delete_runs: self.smart_delete_runs,
+            preserve_removed: self.preserve_removed,
+            fail_on_missing_checksums: self.fail_on_missing_checksums,
+            force_redownload: self.force_redownload,
+            delete_only: self.delete_only,
+            output_tsv: self.output_tsv,
+            output_changed_packages_only: self.output_changed_packages_only,
+            changed_packages_path: self.changed_packages_path.clone(),
+            preload_repodata: self.preload_repodata,
+            metrics_port: self.metrics_port,
+            webhook_url: self.webhook_url.clone(),
+            webhook_on_failure_only: self.webhook_on_failure_only,
+            stats_db_path: self.stats_db_path.clone(),
+            since: self.since,
+            channel_prune_age_days: self.channel_prune_age_days,
+            with_deps: self.with_deps,
+            exclude_prerelease: self.exclude_prerelease,
+            max_packages: self.max_packages,
+            package_name_transform_command: self.package_name_transform_command.clone(),
+            hide_progress: self.hide_progress,
+            quiet: self.quiet,
+            resume: self.resume,
+            checkpoint_path: self.checkpoint_path.clone(),
+            sign_packages: self.sign_packages,
+            signing_key_path: self.signing_key_path.clone(),
+            signing_key_id: self.signing_key_id.clone(),
+            bearer_token: self.bearer_token.clone(),
+            quetz_token: self.quetz_token.clone(),
+            quetz_token_location: self.quetz_token_location,
+            conda_token: self.conda_token.clone(),
+            source_display_name: self.source_display_name.clone(),
+            custom_filters: self.custom_filters.clone(),
+        })
+    }
+}
+
+fn is_s3_channel(channel: &NamedChannelOrUrl) -> bool {
+    matches!(channel, NamedChannelOrUrl::Url(url) if url.scheme() == "s3")
+}
+
+fn is_b2_channel(channel: &NamedChannelOrUrl) -> bool {
+    matches!(channel, NamedChannelOrUrl::Url(url) if url.scheme() == "b2")
+}
+
+fn is_local_channel(channel: &NamedChannelOrUrl) -> bool {
+    match channel {
+        NamedChannelOrUrl::Path(_) => true,
+        NamedChannelOrUrl::Url(url) => url.scheme() == "file",
+        NamedChannelOrUrl::Name(_) => false,
+    }
+}
+
+fn is_http_channel(channel: &NamedChannelOrUrl) -> bool {
+    matches!(channel, NamedChannelOrUrl::Url(url) if matches!(url.scheme(), "http" | "https"))
 }
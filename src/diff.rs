@@ -0,0 +1,101 @@
+use miette::IntoDiagnostic;
+use opendal::Operator;
+use rattler_conda_types::{PackageRecord, Platform, RepoData};
+use std::collections::{HashMap, HashSet};
+
+/// Differences between the packages selected from the source and the packages in the destination
+/// of a subdir.
+#[derive(Debug, Clone, Default)]
+pub struct RepoDataDiff {
+    /// Selected packages that don't exist in the destination.
+    pub only_in_source: HashMap<String, PackageRecord>,
+    /// Packages in the destination that are not selected anymore, with their destination record.
+    pub only_in_dest: HashMap<String, PackageRecord>,
+    /// Packages in both whose SHA256 differs, as `(source, destination)` records.
+    pub checksum_mismatch: HashMap<String, (PackageRecord, PackageRecord)>,
+    /// Package files in the destination that are neither selected nor listed in its repodata,
+    /// e.g. left behind by an interrupted run.
+    pub untracked_in_dest: HashSet<String>,
+}
+
+impl RepoDataDiff {
+    /// Compares the `selected` source packages with the package files in the destination.
+    ///
+    /// `dest_records` are the records from the repodata of the destination, which may be missing
+    /// for some files.
+    pub(crate) fn new<'a>(
+        selected: impl IntoIterator<Item = (&'a String, &'a PackageRecord)>,
+        available: &HashSet<String>,
+        dest_records: &HashMap<String, PackageRecord>,
+    ) -> Self {
+        let mut diff = RepoDataDiff::default();
+        let mut selected_filenames = HashSet::new();
+        for (filename, record) in selected {
+            selected_filenames.insert(filename);
+            if !available.contains(filename) {
+                diff.only_in_source.insert(filename.clone(), record.clone());
+            } else if let Some(dest_record) = dest_records.get(filename)
+                && let (Some(source_sha256), Some(dest_sha256)) =
+                    (record.sha256, dest_record.sha256)
+                && source_sha256 != dest_sha256
+            {
+                diff.checksum_mismatch
+                    .insert(filename.clone(), (record.clone(), dest_record.clone()));
+            }
+        }
+        for filename in available.iter().filter(|f| !selected_filenames.contains(f)) {
+            match dest_records.get(filename) {
+                Some(record) => {
+                    diff.only_in_dest.insert(filename.clone(), record.clone());
+                }
+                None => {
+                    diff.untracked_in_dest.insert(filename.clone());
+                }
+            }
+        }
+        diff
+    }
+
+    /// Whether the package has to be copied to the destination.
+    pub fn needs_transfer(&self, filename: &str) -> bool {
+        self.only_in_source.contains_key(filename) || self.checksum_mismatch.contains_key(filename)
+    }
+
+    /// The package files that have to be deleted from the destination.
+    pub fn packages_to_delete(&self) -> Vec<String> {
+        self.only_in_dest
+            .keys()
+            .chain(self.untracked_in_dest.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// Reads the package records from the repodata of `subdir` in the destination, if there is one.
+pub(crate) async fn read_destination_records(
+    op: &Operator,
+    subdir: Platform,
+) -> miette::Result<HashMap<String, PackageRecord>> {
+    let repodata_path = format!("{}/repodata.json", subdir.as_str());
+    if !op.exists(&repodata_path).await.into_diagnostic()? {
+        return Ok(HashMap::new());
+    }
+    let repodata_bytes = op.read(&repodata_path).await.into_diagnostic()?.to_vec();
+    let repodata: RepoData = match serde_json::from_slice(&repodata_bytes) {
+        Ok(repodata) => repodata,
+        Err(e) => {
+            // The repodata is written again after mirroring, so this isn't fatal.
+            tracing::warn!(
+                "Could not parse {} of the destination, ignoring it: {}",
+                repodata_path,
+                e
+            );
+            return Ok(HashMap::new());
+        }
+    };
+    Ok(repodata
+        .packages
+        .into_iter()
+        .chain(repodata.conda_packages)
+        .collect())
+}
@@ -0,0 +1,145 @@
+use rattler_conda_types::{PackageRecord, Platform, RepoData};
+use std::collections::HashSet;
+
+use crate::{
+    config::{CondaMirrorConfig, MirrorMode},
+    get_packages_to_mirror,
+};
+
+/// Number of excluded packages that are listed in the report.
+const EXCLUDED_SAMPLE_SIZE: usize = 10;
+
+/// How the include/exclude patterns of a subdir apply to its repodata.
+#[derive(Debug, Clone)]
+pub struct FilterReport {
+    pub subdir: Platform,
+    /// Number of packages in the repodata.
+    pub total: usize,
+    /// Number of packages matching an include pattern, or all patterns of `intersection`.
+    pub matching_include: usize,
+    /// Number of packages matching an exclude pattern.
+    pub matching_exclude: usize,
+    /// Number of packages that would be mirrored.
+    pub to_mirror: usize,
+    /// The first excluded packages by filename, with the reason why they are excluded.
+    pub excluded: Vec<(String, String)>,
+}
+
+/// Applies the filters of `subdir` to `repodata` without touching the destination.
+pub fn filter_report(
+    repodata: &RepoData,
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+) -> FilterReport {
+    let mode = config.mode_for(subdir);
+    let all_packages = repodata
+        .packages
+        .iter()
+        .chain(repodata.conda_packages.iter())
+        .collect::<Vec<_>>();
+    let to_mirror = get_packages_to_mirror(repodata, config, subdir, &HashSet::new());
+
+    let matching_include = all_packages
+        .iter()
+        .filter(|(_, record)| matches_include(mode, record))
+        .count();
+    let matching_exclude = all_packages
+        .iter()
+        .filter(|(_, record)| {
+            mode.exclude_patterns()
+                .iter()
+                .any(|p| p.matches((*record).clone()))
+        })
+        .count();
+
+    let mut excluded = all_packages
+        .iter()
+        .filter(|(filename, _)| !to_mirror.contains_key(*filename))
+        .map(|(filename, record)| ((*filename).clone(), exclusion_reason(config, mode, record)))
+        .collect::<Vec<_>>();
+    excluded.sort();
+    excluded.truncate(EXCLUDED_SAMPLE_SIZE);
+
+    FilterReport {
+        subdir,
+        total: all_packages.len(),
+        matching_include,
+        matching_exclude,
+        to_mirror: to_mirror.len(),
+        excluded,
+    }
+}
+
+fn matches_include(mode: &MirrorMode, record: &PackageRecord) -> bool {
+    match mode {
+        MirrorMode::Intersection(patterns) => patterns.iter().all(|p| p.matches(record.clone())),
+        _ => mode
+            .include_patterns()
+            .iter()
+            .any(|p| p.matches(record.clone())),
+    }
+}
+
+fn exclusion_reason(
+    config: &CondaMirrorConfig,
+    mode: &MirrorMode,
+    record: &PackageRecord,
+) -> String {
+    if let Some(pattern) = mode
+        .exclude_patterns()
+        .iter()
+        .find(|p| p.matches(record.clone()))
+    {
+        return format!("matches exclude `{pattern}`");
+    }
+    if let MirrorMode::Intersection(patterns) = mode
+        && let Some(pattern) = patterns.iter().find(|p| !p.matches(record.clone()))
+    {
+        return format!("does not match intersection `{pattern}`");
+    }
+    if let MirrorMode::OnlyInclude(_) = mode
+        && !matches_include(mode, record)
+    {
+        return "does not match any include pattern".to_string();
+    }
+    if let (Some(since), Some(timestamp)) = (config.since, record.timestamp)
+        && timestamp < since
+    {
+        return format!("built before {since}");
+    }
+    if let (Some(prune_before), Some(timestamp)) = (config.prune_before(), record.timestamp)
+        && timestamp < prune_before
+    {
+        return format!(
+            "older than {} days",
+            config.channel_prune_age_days.unwrap_or_default()
+        );
+    }
+    "removed by another filter".to_string()
+}
+
+/// Formats the report for printing.
+pub fn format_filter_report(report: &FilterReport) -> String {
+    let mut output = format!(
+        "Filters applied to {}\n{:<22} {:>8}\n{:<22} {:>8}\n{:<22} {:>8}\n{:<22} {:>8}\n",
+        report.subdir,
+        "Packages in repodata:",
+        report.total,
+        "Matching include:",
+        report.matching_include,
+        "Matching exclude:",
+        report.matching_exclude,
+        "Packages to mirror:",
+        report.to_mirror,
+    );
+    if !report.excluded.is_empty() {
+        output.push_str(&format!(
+            "\nFirst {} excluded packages:\n",
+            report.excluded.len()
+        ));
+        for (filename, reason) in &report.excluded {
+            output.push_str(&format!("  {filename}  ({reason})\n"));
+        }
+    }
+    output
+}
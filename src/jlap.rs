@@ -0,0 +1,145 @@
+use blake2::digest::{FixedOutput, Update};
+use miette::IntoDiagnostic;
+use opendal::Operator;
+use rattler_conda_types::Platform;
+use rattler_digest::{Blake2b256, Blake2bMac256, compute_bytes_digest};
+use serde_json::{Value, json};
+
+/// Name of the JLAP file next to `repodata.json`, this is where clients look for it.
+const JLAP_FILE_NAME: &str = "repodata.jlap";
+
+/// Initialization vector of a new JLAP file.
+const JLAP_START_INITIALIZATION_VECTOR: [u8; 32] = [0; 32];
+
+/// Appends the difference between `old_repodata` and `new_repodata` to `repodata.jlap` in the
+/// subdir.
+///
+/// A JLAP file consists of the hex-encoded initialization vector, one JSON patch per line, a
+/// footer with the hash of the latest `repodata.json` and a checksum. Every line is hashed with
+/// the hash of the previous line as key, the last hash is the checksum.
+///
+/// If the existing JLAP file doesn't end at `old_repodata`, e.g. because it was modified by someone
+/// else, a new JLAP file is started.
+pub(crate) async fn write_jlap(
+    op: &Operator,
+    subdir: Platform,
+    old_repodata: Option<&[u8]>,
+    new_repodata: &[u8],
+) -> miette::Result<()> {
+    let jlap_path = format!("{}/{}", subdir.as_str(), JLAP_FILE_NAME);
+    let new_hash = hex::encode(compute_bytes_digest::<Blake2b256>(new_repodata));
+
+    let existing_lines = if op.exists(&jlap_path).await.into_diagnostic()? {
+        let jlap = op.read(&jlap_path).await.into_diagnostic()?.to_vec();
+        String::from_utf8(jlap)
+            .into_diagnostic()?
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
+    let mut lines = match old_repodata {
+        Some(old_repodata) => {
+            let old_hash = hex::encode(compute_bytes_digest::<Blake2b256>(old_repodata));
+            if old_hash == new_hash {
+                tracing::debug!("repodata.json in {} did not change", subdir);
+                return Ok(());
+            }
+            let mut lines = match jlap_body(&existing_lines, &old_hash) {
+                Some(body) => body.to_vec(),
+                None => {
+                    tracing::info!("Starting a new {} in {}", JLAP_FILE_NAME, subdir);
+                    vec![hex::encode(JLAP_START_INITIALIZATION_VECTOR)]
+                }
+            };
+            let old_value: Value = serde_json::from_slice(old_repodata).into_diagnostic()?;
+            let new_value: Value = serde_json::from_slice(new_repodata).into_diagnostic()?;
+            let patch = json_patch::diff(&old_value, &new_value);
+            let patch_line = json!({"to": new_hash, "from": old_hash, "patch": patch});
+            lines.push(serde_json::to_string(&patch_line).into_diagnostic()?);
+            lines
+        }
+        None => vec![hex::encode(JLAP_START_INITIALIZATION_VECTOR)],
+    };
+
+    let footer = json!({"url": "repodata.json", "latest": new_hash});
+    lines.push(serde_json::to_string(&footer).into_diagnostic()?);
+    lines.push(checksum(&lines)?);
+
+    op.write(&jlap_path, lines.join("\n"))
+        .await
+        .into_diagnostic()?;
+    tracing::info!("Wrote {} in {}", JLAP_FILE_NAME, subdir);
+    Ok(())
+}
+
+/// Returns the initialization vector and patch lines of an existing JLAP file if its footer points
+/// to `latest_hash`.
+fn jlap_body<'a>(lines: &'a [String], latest_hash: &str) -> Option<&'a [String]> {
+    // initialization vector + footer + checksum
+    if lines.len() < 3 {
+        return None;
+    }
+    let footer: Value = serde_json::from_str(&lines[lines.len() - 2]).ok()?;
+    if footer.get("latest")?.as_str()? != latest_hash {
+        return None;
+    }
+    Some(&lines[..lines.len() - 2])
+}
+
+/// Computes the hash chain over all lines after the initialization vector.
+fn checksum(lines: &[String]) -> miette::Result<String> {
+    let mut hash = hex::decode(&lines[0]).into_diagnostic()?;
+    for line in &lines[1..] {
+        let mut state = Blake2bMac256::new_with_salt_and_personal(&hash, &[], &[])
+            .map_err(|e| miette::miette!("Invalid JLAP key: {}", e))?;
+        state.update(line.as_bytes());
+        hash = state.finalize_fixed().to_vec();
+    }
+    Ok(hex::encode(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn checksum_chains_keyed_hashes() {
+        let iv = hex::encode(JLAP_START_INITIALIZATION_VECTOR);
+        // Computed with Python: hashlib.blake2b(line, key=previous, digest_size=32)
+        assert_eq!(
+            checksum(&lines(&[&iv, r#"{"to":"bb","from":"aa","patch":[]}"#])).unwrap(),
+            "f1a40634accf91bd9e598944f27fe9a7e03f57809ae979aa048ebd766a81edb7"
+        );
+        assert_eq!(
+            checksum(&lines(&[
+                &iv,
+                r#"{"to":"bb","from":"aa","patch":[]}"#,
+                r#"{"url":"repodata.json","latest":"bb"}"#,
+            ]))
+            .unwrap(),
+            "7b95ea01a807cc3719735919c500caea13fdd7fcea2e27e7ee345a3ddfe16f78"
+        );
+        assert_eq!(checksum(&lines(&[&iv])).unwrap(), iv);
+    }
+
+    #[test]
+    fn jlap_body_requires_matching_footer() {
+        let iv = hex::encode(JLAP_START_INITIALIZATION_VECTOR);
+        let jlap = lines(&[
+            &iv,
+            r#"{"to":"bb","from":"aa","patch":[]}"#,
+            r#"{"url":"repodata.json","latest":"bb"}"#,
+            "7b95ea01a807cc3719735919c500caea13fdd7fcea2e27e7ee345a3ddfe16f78",
+        ]);
+        assert_eq!(jlap_body(&jlap, "bb"), Some(&jlap[..2]));
+        assert_eq!(jlap_body(&jlap, "aa"), None);
+        assert_eq!(jlap_body(&jlap[..2], "bb"), None);
+    }
+}
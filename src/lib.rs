@@ -1,42 +1,712 @@
-use futures::{StreamExt, stream::FuturesUnordered};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use chrono::Utc;
+use futures::{StreamExt, future::join_all, stream::FuturesUnordered};
+use indicatif::{
+    HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle,
+};
 use miette::IntoDiagnostic;
-use opendal::{Configurator, Operator, layers::RetryLayer};
+use opendal::{Buffer, Configurator, Operator, layers::RetryLayer};
 use rattler_conda_types::{
-    ChannelConfig, NamedChannelOrUrl, PackageRecord, Platform, RepoData, package::ArchiveType,
+    ChannelConfig, MatchSpec, Matches, NamedChannelOrUrl, PackageName, PackageRecord,
+    ParseStrictness, Platform, RepoData, Version, package::ArchiveType,
 };
 use rattler_digest::{Sha256Hash, compute_bytes_digest};
 use rattler_index::write_repodata;
 use rattler_networking::{
     Authentication, AuthenticationMiddleware, AuthenticationStorage, S3Middleware,
     authentication_storage::{StorageBackend, backends::memory::MemoryStorage},
-    retry_policies::ExponentialBackoff,
+    retry_policies::{ExponentialBackoff, Jitter},
     s3_middleware::S3Config,
 };
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, reqwest::Client};
+use reqwest_middleware::{
+    ClientBuilder, ClientWithMiddleware,
+    reqwest::{Client, redirect::Policy},
+};
 use reqwest_retry::RetryTransientMiddleware;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     env::current_dir,
-    path::PathBuf,
-    sync::Arc,
-    time::Duration,
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
 };
 use tokio::{io::AsyncReadExt, sync::Semaphore};
+use tokio_util::sync::CancellationToken;
+#[cfg(feature = "opentelemetry")]
+use tracing::Instrument;
+use url::Url;
 
+mod aliases;
+mod bandwidth;
+mod channeldata;
+pub mod checkpoint;
+mod circuit_breaker;
+pub mod completeness;
+mod condarc;
 pub mod config;
-use config::{CondaMirrorConfig, MirrorMode};
+pub mod diff;
+pub mod filter_report;
+mod jlap;
+mod listing;
+mod manifest;
+mod metrics;
+mod middleware;
+pub mod migrate;
+mod package_cache;
+pub mod plan;
+mod s3_copy;
+pub mod signing;
+pub mod stats;
+pub mod stats_db;
+mod transform;
+mod tsv;
+pub mod verify;
+mod webhook;
+use aliases::write_subdir_aliases;
+use bandwidth::BandwidthLimiter;
+use channeldata::write_channeldata;
+use checkpoint::{Checkpoint, DEFAULT_CHECKPOINT_PATH};
+use circuit_breaker::CircuitBreakerLayer;
+use completeness::CompletenessReport;
+use config::{CondaMirrorConfig, ConflictResolution, MirrorMode, SourceType};
+use diff::{RepoDataDiff, read_destination_records};
+use filter_report::{FilterReport, filter_report};
+use jlap::write_jlap;
+use listing::{destination_listing_path, write_package_listing};
+use manifest::write_manifest;
+use metrics::start_metrics_server;
+use middleware::{BearerTokenMiddleware, CondaTokenMiddleware, QuetzTokenMiddleware};
+use package_cache::{package_cache_repodata, package_cache_subdirs};
+use plan::MirrorPlan;
+use s3_copy::S3Copy;
+use signing::PackageSigner;
+use stats::{ActiveTask, MirrorCounters, MirrorStats, MirrorSubdirDiff};
+use stats_db::{record_run, update_absent_packages};
+use transform::transform_package_names;
+use tsv::{PackageAction, TSV_HEADER, print_tsv_row};
+use verify::{VerifyReport, verify_subdir};
+use webhook::Webhook;
+
+/// Number of retries of failed requests to the source if none is configured.
+const DEFAULT_HTTP_MAX_RETRIES: u32 = 12;
+
+/// Upper bound for the delay between two retries of a request.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Number of packages that are transferred at the same time.
+const DEFAULT_MAX_PARALLEL: usize = 10;
+
+/// Number of packages that are transferred at the same time to B2 destinations.
+const B2_MAX_PARALLEL: usize = 4;
+
+/// User-Agent header of the HTTP client if none is configured.
+const DEFAULT_USER_AGENT: &str = concat!("conda-mirror/", env!("CARGO_PKG_VERSION"));
+
+/// Read timeout of the HTTP client if none is configured.
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 120;
+
+/// Timeout of the HEAD requests probing for subdirs if none is configured.
+const DEFAULT_SOURCE_PROBE_TIMEOUT_MS: u64 = 5000;
+
+/// Maximum fraction of existing packages in a subdir that may be deleted without `--force-delete`.
+const DEFAULT_MAX_DELETE_FRACTION: f64 = 0.2;
+
+/// Number of the slowest package downloads that are logged after a subdir was mirrored.
+const SLOWEST_DOWNLOADS_REPORTED: usize = 10;
+
+/// Free space local destinations need before adding packages, relative to their total size.
+const MIN_FREE_SPACE_FACTOR: f64 = 1.1;
+
+/// Prefix that `--soft-delete` moves deleted packages to if none is configured.
+const DEFAULT_TRASH_PREFIX: &str = "_trash/";
+
+/// Consecutive storage errors after which the circuit breaker opens, if it is enabled.
+const DEFAULT_CIRCUIT_BREAKER_THRESHOLD: usize = 5;
+
+/// Time in seconds the circuit breaker stays open before storage operations are tried again.
+const DEFAULT_CIRCUIT_BREAKER_COOLDOWN_SECS: u64 = 30;
 
 #[derive(Clone, Debug)]
 #[allow(clippy::large_enum_variant)]
 enum OpenDALConfigurator {
+    B2(opendal::services::B2Config),
     File(opendal::services::FsConfig),
     S3(opendal::services::S3Config),
+    #[cfg(unix)]
+    Sftp(opendal::services::SftpConfig),
+    WebDav(opendal::services::WebdavConfig),
 }
 
-pub async fn mirror(config: CondaMirrorConfig) -> miette::Result<()> {
+impl OpenDALConfigurator {
+    fn operator(
+        &self,
+        retry_layer: RetryLayer,
+        circuit_breaker: Option<CircuitBreakerLayer>,
+    ) -> miette::Result<Operator> {
+        match self {
+            OpenDALConfigurator::B2(opendal_config) => {
+                create_operator_with_layers(opendal_config.clone(), retry_layer, circuit_breaker)
+            }
+            OpenDALConfigurator::File(opendal_config) => {
+                create_operator_with_layers(opendal_config.clone(), retry_layer, circuit_breaker)
+            }
+            OpenDALConfigurator::S3(opendal_config) => {
+                create_operator_with_layers(opendal_config.clone(), retry_layer, circuit_breaker)
+            }
+            #[cfg(unix)]
+            OpenDALConfigurator::Sftp(opendal_config) => {
+                create_operator_with_layers(opendal_config.clone(), retry_layer, circuit_breaker)
+            }
+            OpenDALConfigurator::WebDav(opendal_config) => {
+                create_operator_with_layers(opendal_config.clone(), retry_layer, circuit_breaker)
+            }
+        }
+    }
+}
+
+/// Returns the destination backends that are compiled into this binary together with the URL
+/// schemes they handle.
+pub fn destination_backends() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![
+        ("b2", &["b2"]),
+        ("filesystem", &["file"]),
+        ("s3", &["s3"]),
+        #[cfg(unix)]
+        ("sftp", &["sftp"]),
+        ("webdav", &["http", "https"]),
+    ]
+}
+
+/// Mirrors the source channel to the destination.
+///
+/// Once `cancellation_token` is cancelled, no new packages are transferred and the packages that
+/// are currently in flight are finished. The subdirs that were not mirrored completely are then
+/// recorded in a checkpoint file so that the next run can resume with `--resume`.
+#[cfg_attr(
+    feature = "opentelemetry",
+    tracing::instrument(name = "mirror", skip_all, fields(destination = %config.destination))
+)]
+pub async fn mirror(
+    config: CondaMirrorConfig,
+    cancellation_token: CancellationToken,
+) -> miette::Result<MirrorStats> {
     let client = get_client(&config)?;
+    let webhook = config
+        .webhook_url
+        .clone()
+        .map(|url| Webhook::new(url, &config));
+    let result = mirror_with_client(config, client.clone(), cancellation_token).await;
+    if let Some(webhook) = webhook {
+        webhook.notify(&client, &result).await;
+    }
+    result
+}
+
+async fn mirror_with_client(
+    config: CondaMirrorConfig,
+    client: ClientWithMiddleware,
+    cancellation_token: CancellationToken,
+) -> miette::Result<MirrorStats> {
+    let start = Instant::now();
+    let started_at = Utc::now();
+
+    for source in config.sources() {
+        config.for_source(source).check_local_source()?;
+    }
+
+    let mut destination_operators = Vec::new();
+    for destination in config.destinations() {
+        let destination_config = config.for_destination(destination);
+        let opendal_config = destination_opendal_config(&destination_config)?;
+        tracing::info!("Using opendal config: {:?}", opendal_config);
+        let op = opendal_config.operator(
+            opendal_retry_layer(&config),
+            opendal_circuit_breaker_layer(&config),
+        )?;
+        destination_operators.push((destination_config, opendal_config, op));
+    }
+
+    if !config.quiet {
+        eprintln!(
+            "🪞 Mirroring {} to {}...",
+            config.source_display_name(),
+            config
+                .destinations()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if config.output_tsv {
+        println!("{TSV_HEADER}");
+    }
+
+    let checkpoint_path = config
+        .checkpoint_path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CHECKPOINT_PATH));
+    let mut checkpoint = if config.resume {
+        let checkpoint = Checkpoint::load(&checkpoint_path, &config.source, &config.destination)?;
+        if !config.quiet {
+            eprintln!(
+                "⏩ Resuming from {}, skipping {} completed subdirs",
+                checkpoint_path.display(),
+                checkpoint.completed.len()
+            );
+        }
+        checkpoint
+    } else {
+        Checkpoint::new(&config.source, &config.destination)
+    };
+
+    let source_subdirs = get_source_subdirs(&config).await?;
+    let mut subdirs = Vec::new();
+    for subdir in source_subdirs.iter().flat_map(|(_, subdirs)| subdirs) {
+        if !subdirs.contains(subdir) && !checkpoint.completed.contains(subdir) {
+            subdirs.push(*subdir);
+        }
+    }
+    tracing::info!("Mirroring the following subdirs: {:?}", subdirs);
+    checkpoint.pending = subdirs.clone();
+
+    let preloaded_repodata = if config.preload_repodata {
+        let preloaded_repodata =
+            preload_repodata(&config, &client, &source_subdirs, &subdirs).await?;
+        tracing::info!("Preloaded {} repodata files", preloaded_repodata.len());
+        preloaded_repodata
+    } else {
+        HashMap::new()
+    };
+
+    // B2 limits the number of concurrent uploads per bucket more strictly than S3.
+    let max_parallel = if destination_operators
+        .iter()
+        .any(|(_, opendal_config, _)| matches!(opendal_config, OpenDALConfigurator::B2(_)))
+    {
+        B2_MAX_PARALLEL
+    } else {
+        DEFAULT_MAX_PARALLEL
+    };
+    let mut ctx = MirrorContext::new(
+        config.clone(),
+        client,
+        source_subdirs,
+        Arc::new(multi_progress(&config)),
+        Arc::new(Semaphore::new(max_parallel)),
+        cancellation_token.clone(),
+    )?;
+    ctx.preloaded_repodata = Arc::new(Mutex::new(preloaded_repodata));
+    let mut destinations = Vec::new();
+    for (destination_config, opendal_config, op) in destination_operators {
+        let s3_copy = match &opendal_config {
+            OpenDALConfigurator::S3(opendal_s3_config) => {
+                S3Copy::new(&destination_config, opendal_s3_config)?.map(Arc::new)
+            }
+            _ => None,
+        };
+        let local_root = match &opendal_config {
+            OpenDALConfigurator::File(fs_config) => fs_config.root.clone().map(PathBuf::from),
+            _ => None,
+        };
+        destinations.push(MirrorDestination {
+            ctx: MirrorContext {
+                config: destination_config,
+                s3_copy,
+                ..ctx.clone()
+            },
+            op,
+            local_root,
+        });
+    }
+    let destinations = Arc::new(destinations);
+
+    // Keep the server alive until the end of the run, it is stopped when dropped.
+    let _metrics_server = match config.metrics_port {
+        Some(port) => Some(start_metrics_server(port, ctx.counters.clone()).await?),
+        None => None,
+    };
+
+    let mut subdir_diffs = Vec::new();
+    // Destinations that failed while others succeeded, as `(destination/subdir, error)`.
+    let mut failures = Vec::new();
+    // All subdirs are planned before anything is transferred, so that the total download size is
+    // known and the free space of local destinations is checked only once.
+    let mut plan_tasks = FuturesUnordered::new();
+    for subdir in subdirs {
+        let destinations = destinations.clone();
+        let task = async move { plan_subdir_destinations(&destinations, subdir).await };
+        plan_tasks.push(tokio::spawn(task));
+    }
+    let mut all_subdir_plans = Vec::new();
+    while let Some(join_result) = plan_tasks.next().await {
+        match join_result {
+            Ok(Ok(subdir_plans)) => all_subdir_plans.push(subdir_plans),
+            Ok(Err(e)) => {
+                tracing::error!("Failed to process subdir: {}", e);
+                plan_tasks.clear();
+                return Err(e);
+            }
+            Err(join_err) => {
+                tracing::error!("Task panicked: {}", join_err);
+                plan_tasks.clear();
+                return Err(miette::miette!("Task panicked: {}", join_err));
+            }
+        }
+    }
+    check_disk_space(&config, &destinations, &mut all_subdir_plans)?;
+
+    let mut tasks = FuturesUnordered::new();
+    for subdir_plans in all_subdir_plans {
+        let subdir = subdir_plans.subdir;
+        let destinations = destinations.clone();
+        let aliases = config.subdir_aliases.get(&subdir).cloned();
+        let task = async move {
+            let mut results = mirror_subdir_to_destinations(&destinations, subdir_plans).await?;
+            if let Some(aliases) = aliases {
+                for (destination, result) in destinations.iter().zip(results.iter_mut()) {
+                    if let Ok((true, _)) = result
+                        && let Err(e) = write_subdir_aliases(
+                            &destination.op,
+                            destination.local_root.as_deref(),
+                            subdir,
+                            &aliases,
+                        )
+                        .await
+                    {
+                        *result = Err(e);
+                    }
+                }
+            }
+            // The subdir failed if it could not be mirrored to any destination.
+            if results.iter().all(Result::is_err) {
+                let error = results
+                    .into_iter()
+                    .find_map(Result::err)
+                    .expect("There is at least one destination");
+                return Err(error);
+            }
+            Ok((subdir, results))
+        };
+        tasks.push(tokio::spawn(task));
+    }
+
+    while let Some(join_result) = tasks.next().await {
+        match join_result {
+            Ok(Ok((subdir, results))) => {
+                let mut completed = true;
+                for (destination, result) in config.destinations().zip(results) {
+                    match result {
+                        Ok((destination_completed, diff)) => {
+                            completed &= destination_completed;
+                            subdir_diffs.push(diff);
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Failed to mirror {} to {}: {}",
+                                subdir,
+                                destination,
+                                e
+                            );
+                            failures.push((
+                                format!("{destination}/{}", subdir.as_str()),
+                                e.to_string(),
+                            ));
+                            completed = false;
+                        }
+                    }
+                }
+                if completed {
+                    checkpoint.pending.retain(|pending| *pending != subdir);
+                    checkpoint.completed.push(subdir);
+                }
+            }
+            Ok(Err(e)) => {
+                tracing::error!("Failed to process subdir: {}", e);
+                tasks.clear();
+                return Err(e);
+            }
+            Err(join_err) => {
+                tracing::error!("Task panicked: {}", join_err);
+                tasks.clear();
+                return Err(miette::miette!("Task panicked: {}", join_err));
+            }
+        }
+    }
+
+    if cancellation_token.is_cancelled() && !checkpoint.pending.is_empty() {
+        checkpoint.write(&checkpoint_path)?;
+        return Err(miette::miette!(
+            help = "Run again with --resume to continue where this run stopped",
+            "Mirroring was interrupted, {} subdirs are pending. Wrote checkpoint to {}",
+            checkpoint.pending.len(),
+            checkpoint_path.display()
+        ));
+    }
+    if config.resume {
+        // The checkpoint has been fully processed, make sure it isn't picked up again.
+        std::fs::remove_file(&checkpoint_path).into_diagnostic()?;
+    }
+
+    for destination in destinations.iter() {
+        match write_channeldata(&destination.op, &checkpoint.completed).await {
+            Err(e) if destinations.len() > 1 => {
+                tracing::error!(
+                    "Failed to write channeldata.json to {}: {}",
+                    destination.ctx.config.destination,
+                    e
+                );
+                failures.push((
+                    format!("{}/channeldata.json", destination.ctx.config.destination),
+                    e.to_string(),
+                ));
+            }
+            result => result?,
+        }
+    }
+    if let Some(path) = &config.package_listing_path {
+        for (index, destination) in destinations.iter().enumerate() {
+            let path = destination_listing_path(path, index, destinations.len());
+            match write_package_listing(&destination.op, &checkpoint.completed, &path).await {
+                Err(e) if destinations.len() > 1 => {
+                    tracing::error!(
+                        "Failed to write package listing of {}: {}",
+                        destination.ctx.config.destination,
+                        e
+                    );
+                    failures.push((path.display().to_string(), e.to_string()));
+                }
+                result => result?,
+            }
+        }
+    }
+
+    let mut stats = ctx.counters.to_stats();
+    stats.subdirs_mirrored = checkpoint.completed;
+    stats.failures = failures;
+    subdir_diffs.sort_by_key(|diff: &MirrorSubdirDiff| diff.subdir.as_str());
+    stats.subdir_diffs = subdir_diffs;
+    stats.duration = start.elapsed();
+
+    if let Some(stats_db_path) = &config.stats_db_path {
+        let sources = config
+            .sources()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        record_run(
+            stats_db_path,
+            started_at,
+            &sources.join(", "),
+            &config
+                .destinations()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            &stats,
+        )
+        .await?;
+    }
+
+    if config.output_changed_packages_only {
+        match &config.changed_packages_path {
+            Some(path) => std::fs::write(path, stats.changed_packages()).map_err(|e| {
+                miette::miette!(
+                    "Could not write changed packages to {}: {}",
+                    path.display(),
+                    e
+                )
+            })?,
+            None => print!("{}", stats.changed_packages()),
+        }
+    }
+
+    if !config.quiet {
+        eprint!("{}", stats.subdir_table());
+        eprintln!("✅ Mirroring completed: {}", stats.summary());
+    }
+    Ok(stats)
+}
+
+fn multi_progress(config: &CondaMirrorConfig) -> MultiProgress {
+    if config.hide_progress || config.quiet {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    }
+}
+
+/// State that is shared between all tasks of a mirror run.
+#[derive(Clone)]
+struct MirrorContext {
+    config: CondaMirrorConfig,
+    client: ClientWithMiddleware,
+    /// The sources together with the subdirs they provide.
+    source_subdirs: Arc<Vec<(NamedChannelOrUrl, Vec<Platform>)>>,
+    /// Repodata that was fetched up front with `--preload-repodata`, by source and subdir.
+    preloaded_repodata: Arc<Mutex<PreloadedRepoData>>,
+    progress: Arc<MultiProgress>,
+    semaphore: Arc<Semaphore>,
+    cancellation_token: CancellationToken,
+    counters: Arc<MirrorCounters>,
+    signer: Option<Arc<PackageSigner>>,
+    /// Set if packages can be copied server-side because source and destination share a bucket.
+    s3_copy: Option<Arc<S3Copy>>,
+    bandwidth_limiter: Option<Arc<BandwidthLimiter>>,
+}
+
+/// A destination of a mirror run.
+#[derive(Clone)]
+struct MirrorDestination {
+    /// Context of the tasks for this destination, its configuration has it as `destination`.
+    ctx: MirrorContext,
+    op: Operator,
+    /// Root directory of local destinations, subdir aliases are symlinked in it.
+    local_root: Option<PathBuf>,
+}
+
+impl MirrorContext {
+    fn new(
+        config: CondaMirrorConfig,
+        client: ClientWithMiddleware,
+        source_subdirs: Vec<(NamedChannelOrUrl, Vec<Platform>)>,
+        progress: Arc<MultiProgress>,
+        semaphore: Arc<Semaphore>,
+        cancellation_token: CancellationToken,
+    ) -> miette::Result<Self> {
+        let signer = if config.sign_packages {
+            let key_path = config
+                .signing_key_path
+                .as_ref()
+                .ok_or(miette::miette!("No signing key path set"))?;
+            let key_id = config
+                .signing_key_id
+                .clone()
+                .ok_or(miette::miette!("No signing key id set"))?;
+            Some(Arc::new(PackageSigner::from_key_file(key_path, key_id)?))
+        } else {
+            None
+        };
+        let bandwidth_limiter = config
+            .bandwidth_limit_bytes_per_sec
+            .map(|bytes_per_sec| Arc::new(BandwidthLimiter::new(bytes_per_sec)));
+        Ok(Self {
+            config,
+            client,
+            source_subdirs: Arc::new(source_subdirs),
+            preloaded_repodata: Default::default(),
+            progress,
+            semaphore,
+            cancellation_token,
+            counters: Arc::new(MirrorCounters::default()),
+            signer,
+            s3_copy: None,
+            bandwidth_limiter,
+        })
+    }
+}
+
+/// A request to the source failed because it could not be reached or responded with a server
+/// error. The request is retried with the fallback sources.
+#[derive(Debug)]
+struct SourceUnreachable {
+    url: Url,
+    reason: String,
+}
+
+impl fmt::Display for SourceUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not reach {}: {}", self.url, self.reason)
+    }
+}
+
+impl std::error::Error for SourceUnreachable {}
+
+impl miette::Diagnostic for SourceUnreachable {}
+
+/// Runs `fetch` with the configuration of the source and, if the source can't be reached, with
+/// the configuration of each fallback source in order.
+///
+/// Returns the result together with the configuration of the source that was used, so that the
+/// packages are downloaded from the same source.
+async fn with_fallback_sources<T, F, Fut>(
+    config: &CondaMirrorConfig,
+    fetch: F,
+) -> miette::Result<(T, CondaMirrorConfig)>
+where
+    F: Fn(CondaMirrorConfig) -> Fut,
+    Fut: Future<Output = miette::Result<T>>,
+{
+    let mut error = match fetch(config.clone()).await {
+        Ok(result) => return Ok((result, config.clone())),
+        Err(e) if e.downcast_ref::<SourceUnreachable>().is_some() => e,
+        Err(e) => return Err(e),
+    };
+    for fallback_source in &config.fallback_sources {
+        tracing::warn!("{}, trying fallback source {}", error, fallback_source);
+        let fallback_config = config.for_source(fallback_source);
+        error = match fetch(fallback_config.clone()).await {
+            Ok(result) => return Ok((result, fallback_config)),
+            Err(e) if e.downcast_ref::<SourceUnreachable>().is_some() => e,
+            Err(e) => return Err(e),
+        };
+    }
+    Err(error)
+}
+
+async fn fetch_repodata(
+    config: &CondaMirrorConfig,
+    client: &ClientWithMiddleware,
+    subdir: Platform,
+) -> miette::Result<RepoData> {
+    if config.source_type == SourceType::PackageCache {
+        let path = package_cache_path(config)?;
+        return tokio::task::spawn_blocking(move || package_cache_repodata(&path, subdir))
+            .await
+            .into_diagnostic()?;
+    }
+    let repodata_url = config.repodata_url(subdir)?;
+    let repodata = if repodata_url.scheme() == "file" {
+        RepoData::from_path(
+            repodata_url
+                .to_file_path()
+                .map_err(|_| miette::miette!("Invalid file path: {}", repodata_url))?,
+        )
+        .into_diagnostic()?
+    } else {
+        let response =
+            client
+                .get(repodata_url.clone())
+                .send()
+                .await
+                .map_err(|e| SourceUnreachable {
+                    url: repodata_url.clone(),
+                    reason: e.to_string(),
+                })?;
+        if response.status().is_server_error() {
+            return Err(SourceUnreachable {
+                url: repodata_url,
+                reason: response.status().to_string(),
+            }
+            .into());
+        }
+        if !response.status().is_success() {
+            return Err(miette::miette!(
+                "Failed to fetch repodata: {}",
+                response.status()
+            ));
+        }
+        let text = response.text().await.into_diagnostic()?;
+        tracing::debug!("repodata response body: {}", text);
+        serde_json::from_str(&text).into_diagnostic()?
+    };
+    Ok(repodata)
+}
 
+/// Creates the OpenDAL configuration for the destination channel.
+fn destination_opendal_config(config: &CondaMirrorConfig) -> miette::Result<OpenDALConfigurator> {
     let channel_config = ChannelConfig::default_with_root_dir(current_dir().into_diagnostic()?);
     let dest_channel = config
         .destination
@@ -46,11 +716,10 @@ pub async fn mirror(config: CondaMirrorConfig) -> miette::Result<()> {
     let dest_channel_url = dest_channel.base_url.url();
     let opendal_config = match dest_channel_url.scheme() {
         "file" => {
-            let channel_path_str = dest_channel_url
+            let channel_path = dest_channel_url
                 .to_file_path()
-                .map_err(|_| miette::miette!("Could not convert URL to file path"))?
-                .canonicalize()
-                .map_err(|e| miette::miette!("Could not canonicalize path: {}", e))? // todo: if doesn't exist, create it
+                .map_err(|_| miette::miette!("Could not convert URL to file path"))?;
+            let channel_path_str = canonicalize_or_create(&channel_path)?
                 .to_string_lossy()
                 .to_string();
             let mut config = opendal::services::FsConfig::default();
@@ -71,6 +740,17 @@ pub async fn mirror(config: CondaMirrorConfig) -> miette::Result<()> {
             opendal_s3_config.region = Some(s3_config.region);
             opendal_s3_config.endpoint = Some(s3_config.endpoint_url.to_string());
             opendal_s3_config.enable_virtual_host_style = !s3_config.force_path_style;
+            opendal_s3_config.server_side_encryption =
+                s3_config.sse.map(|sse| sse.as_str().to_string());
+            opendal_s3_config.server_side_encryption_aws_kms_key_id = s3_config.kms_key_id;
+            opendal_s3_config.default_storage_class = s3_config.storage_class;
+            opendal_s3_config.checksum_algorithm = s3_config
+                .checksum_algorithm
+                .map(|algorithm| algorithm.as_opendal_str().to_string());
+            // Without credentials, the role is assumed using the credentials from the environment,
+            // e.g. the instance profile on EC2.
+            opendal_s3_config.role_arn = s3_config.role_arn;
+            opendal_s3_config.external_id = s3_config.external_id;
             // Use credentials from the CLI if they are provided.
             if let Some(s3_credentials) = config.s3_credentials_destination.clone() {
                 opendal_s3_config.secret_access_key = Some(s3_credentials.secret_access_key);
@@ -95,13 +775,74 @@ pub async fn mirror(config: CondaMirrorConfig) -> miette::Result<()> {
                     opendal_s3_config.access_key_id = Some(access_key_id);
                     opendal_s3_config.secret_access_key = Some(secret_access_key);
                     opendal_s3_config.session_token = session_token;
-                } else {
+                } else if opendal_s3_config.role_arn.is_none() {
                     return Err(miette::miette!("Missing S3 credentials"));
                 }
             }
 
             OpenDALConfigurator::S3(opendal_s3_config)
         }
+        #[cfg(unix)]
+        "sftp" => {
+            let host = dest_channel_url
+                .host_str()
+                .ok_or(miette::miette!("No host in SFTP URL"))?;
+            let mut opendal_sftp_config = opendal::services::SftpConfig::default();
+            // The system `ssh` is used for connecting, it understands `ssh://` destinations.
+            opendal_sftp_config.endpoint = Some(match dest_channel_url.port() {
+                Some(port) => format!("ssh://{host}:{port}"),
+                None => format!("ssh://{host}"),
+            });
+            opendal_sftp_config.root = Some(dest_channel_url.path().to_string());
+            if !dest_channel_url.username().is_empty() {
+                opendal_sftp_config.user = Some(dest_channel_url.username().to_string());
+            }
+            opendal_sftp_config.key = config
+                .sftp_private_key_path
+                .as_ref()
+                .map(|path| path.to_string_lossy().to_string());
+            opendal_sftp_config.known_hosts_strategy = Some(
+                if config.sftp_host_key_check {
+                    "strict"
+                } else {
+                    "accept"
+                }
+                .to_string(),
+            );
+            OpenDALConfigurator::Sftp(opendal_sftp_config)
+        }
+        "b2" => {
+            let b2_credentials = config
+                .b2_credentials
+                .clone()
+                .ok_or(miette::miette!("No B2 credentials set"))?;
+            let mut opendal_b2_config = opendal::services::B2Config::default();
+            opendal_b2_config.root = Some(dest_channel_url.path().to_string());
+            opendal_b2_config.bucket = dest_channel_url
+                .host_str()
+                .ok_or(miette::miette!("No bucket in B2 URL"))?
+                .to_string();
+            opendal_b2_config.bucket_id = config
+                .b2_bucket_id
+                .clone()
+                .ok_or(miette::miette!("No B2 bucket ID set"))?;
+            opendal_b2_config.application_key_id = Some(b2_credentials.application_key_id);
+            opendal_b2_config.application_key = Some(b2_credentials.application_key);
+            OpenDALConfigurator::B2(opendal_b2_config)
+        }
+        "http" | "https" if config.webdav => {
+            let mut opendal_webdav_config = opendal::services::WebdavConfig::default();
+            let mut endpoint = Url::clone(dest_channel_url);
+            endpoint.set_path("");
+            opendal_webdav_config.endpoint =
+                Some(endpoint.as_str().trim_end_matches('/').to_string());
+            opendal_webdav_config.root = Some(dest_channel_url.path().to_string());
+            if let Some(webdav_credentials) = config.webdav_credentials.clone() {
+                opendal_webdav_config.username = Some(webdav_credentials.username);
+                opendal_webdav_config.password = Some(webdav_credentials.password);
+            }
+            OpenDALConfigurator::WebDav(opendal_webdav_config)
+        }
         _ => {
             return Err(miette::miette!(
                 "Unsupported scheme in destination: {}",
@@ -109,85 +850,275 @@ pub async fn mirror(config: CondaMirrorConfig) -> miette::Result<()> {
             ));
         }
     };
-    tracing::info!("Using opendal config: {:?}", opendal_config);
+    Ok(opendal_config)
+}
 
-    eprintln!(
-        "🪞 Mirroring {} to {}...",
-        config.source, config.destination
-    );
+/// Preloaded repodata by source and subdir, with the configuration of the source it was fetched
+/// from, which is a fallback source if the source was unreachable.
+type PreloadedRepoData = HashMap<(String, Platform), (RepoData, CondaMirrorConfig)>;
 
-    let subdirs = get_subdirs(&config, client.clone()).await?;
-    tracing::info!("Mirroring the following subdirs: {:?}", subdirs);
+/// Fetches the repodata of the given subdirs of all sources concurrently, together with the
+/// configuration of the source it was fetched from.
+async fn preload_repodata(
+    config: &CondaMirrorConfig,
+    client: &ClientWithMiddleware,
+    source_subdirs: &[(NamedChannelOrUrl, Vec<Platform>)],
+    subdirs: &[Platform],
+) -> miette::Result<PreloadedRepoData> {
+    let mut tasks = FuturesUnordered::new();
+    for (source, source_subdirs) in source_subdirs {
+        for subdir in source_subdirs.iter().filter(|s| subdirs.contains(s)) {
+            let source_config = config.for_source(source);
+            let subdir = *subdir;
+            tasks.push(async move {
+                let (repodata, used_config) =
+                    with_fallback_sources(&source_config, |source_config| async move {
+                        fetch_repodata(&source_config, client, subdir).await
+                    })
+                    .await?;
+                tracing::info!(
+                    "Fetched repo data for subdir {} from {}",
+                    subdir,
+                    used_config.source
+                );
+                Ok::<_, miette::Report>((
+                    (source_config.source.to_string(), subdir),
+                    (repodata, used_config),
+                ))
+            });
+        }
+    }
 
-    let max_parallel = 10;
-    let multi_progress = Arc::new(MultiProgress::new());
-    let semaphore = Arc::new(Semaphore::new(max_parallel));
+    let mut preloaded_repodata = HashMap::new();
+    while let Some(result) = tasks.next().await {
+        let (key, value) = result?;
+        preloaded_repodata.insert(key, value);
+    }
+    Ok(preloaded_repodata)
+}
 
-    let mut tasks = FuturesUnordered::new();
+/// Lists the packages of a subdir that would be mirrored with the given configuration.
+///
+/// This only fetches the repodata of the source channel and doesn't touch the destination.
+pub async fn list_packages(
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+) -> miette::Result<impl Iterator<Item = (String, PackageRecord)>> {
+    let client = get_client(config)?;
+    let repodata = fetch_repodata(config, &client, subdir).await?;
+    Ok(get_packages_to_mirror(&repodata, config, subdir, &HashSet::new()).into_iter())
+}
+
+/// Context for looking at a single subdir without transferring packages.
+fn subdir_context(
+    config: &CondaMirrorConfig,
+    client: &ClientWithMiddleware,
+    subdir: Platform,
+) -> miette::Result<MirrorContext> {
+    let source_subdirs = config
+        .sources()
+        .map(|source| (source.clone(), vec![subdir]))
+        .collect();
+    MirrorContext::new(
+        config.clone(),
+        client.clone(),
+        source_subdirs,
+        Arc::new(MultiProgress::with_draw_target(ProgressDrawTarget::hidden())),
+        Arc::new(Semaphore::new(1)),
+        CancellationToken::new(),
+    )
+}
+
+/// Compares the packages that would be mirrored into `subdir` with the destination `op`, without
+/// changing anything.
+pub async fn diff_channel(
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+    client: &ClientWithMiddleware,
+    op: &Operator,
+) -> miette::Result<RepoDataDiff> {
+    let ctx = subdir_context(config, client, subdir)?;
+    let available_packages = list_available_packages(op, subdir).await?;
+    let source_repodata = fetch_source_repodata(&ctx, subdir).await?;
+    let (_, packages_to_mirror) =
+        select_packages(&ctx, subdir, &available_packages, &source_repodata).await?;
+    Ok(RepoDataDiff::new(
+        packages_to_mirror
+            .iter()
+            .map(|(filename, (package, _))| (filename, package)),
+        &available_packages,
+        &read_destination_records(op, subdir).await?,
+    ))
+}
+
+/// Checks that the packages listed in the repodata of the destination exist and match their size
+/// and SHA256, without contacting the source.
+///
+/// Verifies `config.subdirs` if set, otherwise all subdirs of the destination with a repodata.json.
+pub async fn verify(config: &CondaMirrorConfig) -> miette::Result<VerifyReport> {
+    let op = destination_opendal_config(config)?.operator(
+        opendal_retry_layer(config),
+        opendal_circuit_breaker_layer(config),
+    )?;
+    let subdirs = match &config.subdirs {
+        Some(subdirs) => subdirs.clone(),
+        None => destination_subdirs(&op).await?,
+    };
+    tracing::info!("Verifying the following subdirs: {:?}", subdirs);
+
+    let mut report = VerifyReport::default();
     for subdir in subdirs {
-        let config = config.clone();
-        let client = client.clone();
-        let multi_progress = multi_progress.clone();
-        let semaphore = semaphore.clone();
-        let opendal_config = opendal_config.clone();
-        let task = async move {
-            match &opendal_config {
-                // todo: call mirror_subdir with configurator instead
-                OpenDALConfigurator::File(opendal_config) => {
-                    mirror_subdir(
-                        config.clone(),
-                        opendal_config.clone(),
-                        client.clone(),
-                        subdir,
-                        multi_progress.clone(),
-                        semaphore.clone(),
-                    )
-                    .await // TODO: remove async move and .await
-                }
-                OpenDALConfigurator::S3(opendal_config) => {
-                    mirror_subdir(
-                        config.clone(),
-                        opendal_config.clone(),
-                        client.clone(),
-                        subdir,
-                        multi_progress.clone(),
-                        semaphore.clone(),
-                    )
-                    .await
-                }
-            }
-        };
-        tasks.push(tokio::spawn(task));
+        report.merge(verify_subdir(&op, subdir).await?);
     }
+    Ok(report)
+}
 
-    while let Some(join_result) = tasks.next().await {
-        match join_result {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => {
-                tracing::error!("Failed to process subdir: {}", e);
-                tasks.clear();
-                return Err(e);
-            }
-            Err(join_err) => {
-                tracing::error!("Task panicked: {}", join_err);
-                tasks.clear();
-                return Err(miette::miette!("Task panicked: {}", join_err));
+/// Checks that every package the configuration selects from the source exists in the destination,
+/// without reading the packages.
+///
+/// Checks `config.subdirs` if set, otherwise all subdirs of the sources.
+pub async fn check_completeness(config: &CondaMirrorConfig) -> miette::Result<CompletenessReport> {
+    let client = get_client(config)?;
+    let op = destination_opendal_config(config)?.operator(
+        opendal_retry_layer(config),
+        opendal_circuit_breaker_layer(config),
+    )?;
+    let source_subdirs = get_source_subdirs(config).await?;
+    let mut subdirs = Vec::new();
+    for subdir in source_subdirs.iter().flat_map(|(_, subdirs)| subdirs) {
+        if !subdirs.contains(subdir) {
+            subdirs.push(*subdir);
+        }
+    }
+    tracing::info!("Checking the following subdirs: {:?}", subdirs);
+
+    let ctx = MirrorContext::new(
+        config.clone(),
+        client,
+        source_subdirs,
+        Arc::new(MultiProgress::with_draw_target(ProgressDrawTarget::hidden())),
+        Arc::new(Semaphore::new(1)),
+        CancellationToken::new(),
+    )?;
+    let mut report = CompletenessReport::default();
+    for subdir in subdirs {
+        let available_packages = list_available_packages(&op, subdir).await?;
+        let source_repodata = fetch_source_repodata(&ctx, subdir).await?;
+        let (_, packages_to_mirror) =
+            select_packages(&ctx, subdir, &available_packages, &source_repodata).await?;
+        let diff = RepoDataDiff::new(
+            packages_to_mirror
+                .iter()
+                .map(|(filename, (package, _))| (filename, package)),
+            &available_packages,
+            &read_destination_records(&op, subdir).await?,
+        );
+        report.add_subdir(
+            subdir,
+            &diff,
+            packages_to_mirror.len(),
+            available_packages.len(),
+        );
+    }
+    Ok(report)
+}
+
+/// Generates presigned GET URLs that are valid for `expires_in` for the packages of `subdir` in
+/// the S3 destination, or only for `package` if given.
+pub async fn generate_presigned_urls(
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+    package: Option<&str>,
+    expires_in: Duration,
+) -> miette::Result<Vec<String>> {
+    let opendal_config = destination_opendal_config(config)?;
+    if !matches!(opendal_config, OpenDALConfigurator::S3(_)) {
+        return Err(miette::miette!(
+            "Presigned URLs can only be generated for s3:// destinations"
+        ));
+    }
+    let op = opendal_config.operator(
+        opendal_retry_layer(config),
+        opendal_circuit_breaker_layer(config),
+    )?;
+
+    let mut filenames = match package {
+        Some(package) => {
+            let path = format!("{}/{}", subdir.as_str(), package);
+            if !op.exists(&path).await.into_diagnostic()? {
+                return Err(miette::miette!(
+                    "{} does not exist in {}",
+                    path,
+                    config.destination
+                ));
             }
+            vec![package.to_string()]
         }
+        None => list_available_packages(&op, subdir)
+            .await?
+            .into_iter()
+            .collect(),
+    };
+    filenames.sort();
+
+    let mut urls = Vec::with_capacity(filenames.len());
+    for filename in filenames {
+        let path = format!("{}/{}", subdir.as_str(), filename);
+        let request = op.presign_read(&path, expires_in).await.into_diagnostic()?;
+        urls.push(request.uri().to_string());
     }
+    Ok(urls)
+}
 
-    eprintln!("✅ Mirroring completed");
-    Ok(())
+/// Lists the subdirs of the destination that have a repodata.json.
+async fn destination_subdirs(op: &Operator) -> miette::Result<Vec<Platform>> {
+    let mut subdirs = Vec::new();
+    for entry in op.list("").await.into_diagnostic()? {
+        if !entry.metadata().mode().is_dir() {
+            continue;
+        }
+        let Ok(subdir) = entry.name().trim_end_matches('/').parse::<Platform>() else {
+            continue;
+        };
+        let repodata_path = format!("{}/repodata.json", subdir.as_str());
+        if op.exists(&repodata_path).await.into_diagnostic()? {
+            subdirs.push(subdir);
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Applies the filters of `subdir` to the repodata of the source, or to the repodata.json at
+/// `repodata_path` if given.
+pub async fn test_filters(
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+    repodata_path: Option<&Path>,
+) -> miette::Result<FilterReport> {
+    let repodata = match repodata_path {
+        Some(path) => RepoData::from_path(path)
+            .map_err(|e| miette::miette!("Could not read {}: {}", path.display(), e))?,
+        None => fetch_repodata(config, &get_client(config)?, subdir).await?,
+    };
+    Ok(filter_report(&repodata, config, subdir))
 }
 
+/// Returns the packages of `repodata` that should be mirrored according to the mode of `subdir`.
+///
+/// Packages that are older than `config.since` are only included if they are in `existing`,
+/// packages older than `config.channel_prune_age_days` are never included.
 fn get_packages_to_mirror(
     repodata: &RepoData,
     config: &CondaMirrorConfig,
+    subdir: Platform,
+    existing: &HashSet<String>,
 ) -> HashMap<String, PackageRecord> {
     let mut all_packages = HashMap::new();
     all_packages.extend(repodata.packages.clone());
     all_packages.extend(repodata.conda_packages.clone());
-    match config.mode.clone() {
+    let mode = config.mode_for(subdir);
+    let prune_before = config.prune_before();
+    let mut packages: HashMap<String, PackageRecord> = match mode.clone() {
         MirrorMode::All => all_packages.clone(),
         MirrorMode::OnlyInclude(include) => all_packages
             .clone()
@@ -208,20 +1139,140 @@ fn get_packages_to_mirror(
                 })
             })
             .collect(),
+        MirrorMode::Intersection(patterns) => all_packages
+            .clone()
+            .into_iter()
+            .filter(|pkg| patterns.iter().all(|p| p.matches(pkg.1.clone())))
+            .collect(),
+    };
+    let roots = packages
+        .iter()
+        .filter(|(_, record)| {
+            config.with_deps
+                || mode
+                    .include_patterns()
+                    .iter()
+                    .any(|p| p.with_deps() && p.matches((*record).clone()))
+        })
+        .map(|(filename, _)| filename.clone())
+        .collect::<Vec<_>>();
+    add_dependencies(&mut packages, roots, &all_packages);
+    let packages = packages
+        .into_iter()
+        .filter(|(filename, record)| {
+            config
+                .custom_filters
+                .iter()
+                .all(|filter| filter.keep(filename, record))
+        })
+        .filter(
+            |(filename, record)| match (config.since, record.timestamp) {
+                (Some(since), Some(timestamp)) => timestamp >= since || existing.contains(filename),
+                // Packages without a timestamp can't be excluded.
+                _ => true,
+            },
+        )
+        .filter(|(_, record)| match (prune_before, record.timestamp) {
+            (Some(prune_before), Some(timestamp)) => timestamp >= prune_before,
+            _ => true,
+        })
+        .filter(|(_, record)| !(config.exclude_prerelease && is_prerelease(&record.version)))
+        .collect::<HashMap<_, _>>();
+    match config.max_packages {
+        Some(max_packages) if packages.len() > max_packages => {
+            let mut packages = packages.into_iter().collect::<Vec<_>>();
+            packages.sort_by(|(a, _), (b, _)| a.cmp(b));
+            packages.truncate(max_packages);
+            packages.into_iter().collect()
+        }
+        _ => packages,
+    }
+}
+
+/// Identifiers in a version that mark it as a pre-release, `dev` is a separate component.
+const PRERELEASE_IDENTIFIERS: &[&str] = &["a", "alpha", "b", "beta", "rc"];
+
+/// Whether `version` is a pre-release like `1.0a1`, `2.0.0rc1` or `1.0.dev0`.
+///
+/// A pre-release identifier at the very end of the version is a release suffix instead, e.g. in
+/// `2024b` of tzdata or `1.1.1b` of openssl. The local version after a `+` is ignored.
+fn is_prerelease(version: &Version) -> bool {
+    let components = version
+        .segments()
+        .flat_map(|segment| segment.components())
+        .collect::<Vec<_>>();
+    components.iter().enumerate().any(|(index, component)| {
+        component.is_dev()
+            || (index + 1 < components.len()
+                && component
+                    .as_iden()
+                    .is_some_and(|iden| PRERELEASE_IDENTIFIERS.contains(&iden)))
+    })
+}
+
+/// Adds the packages that `roots` depend on to `packages`, recursively.
+///
+/// Every package of `all_packages` that matches a dependency is added, not only the newest one.
+fn add_dependencies(
+    packages: &mut HashMap<String, PackageRecord>,
+    roots: Vec<String>,
+    all_packages: &HashMap<String, PackageRecord>,
+) {
+    let mut packages_by_name: HashMap<&PackageName, Vec<(&String, &PackageRecord)>> =
+        HashMap::new();
+    for (filename, record) in all_packages {
+        packages_by_name
+            .entry(&record.name)
+            .or_default()
+            .push((filename, record));
+    }
+    let mut resolved_specs = HashSet::new();
+    let mut queue = VecDeque::from(roots);
+    while let Some(filename) = queue.pop_front() {
+        for dependency in &all_packages[&filename].depends {
+            if !resolved_specs.insert(dependency.as_str()) {
+                continue;
+            }
+            let spec = match MatchSpec::from_str(dependency, ParseStrictness::Lenient) {
+                Ok(spec) => spec,
+                Err(e) => {
+                    tracing::warn!("Ignoring dependency {} of {}: {}", dependency, filename, e);
+                    continue;
+                }
+            };
+            // Virtual packages like __glibc are not in the channel.
+            let Some(candidates) = spec.name.as_ref().and_then(|n| packages_by_name.get(n)) else {
+                continue;
+            };
+            for (candidate_filename, candidate) in candidates {
+                if spec.matches(*candidate) && !packages.contains_key(*candidate_filename) {
+                    packages.insert((*candidate_filename).clone(), (*candidate).clone());
+                    queue.push_back((*candidate_filename).clone());
+                }
+            }
+        }
     }
 }
 
+/// Deletes the given packages from `subdir` and returns the filenames of the deleted ones.
 #[allow(clippy::type_complexity)]
+#[cfg_attr(
+    feature = "opentelemetry",
+    tracing::instrument(skip_all, fields(%subdir, packages = packages_to_delete.len()))
+)]
 async fn dispatch_tasks_delete(
     packages_to_delete: Vec<String>,
     subdir: Platform,
-    progress: Arc<MultiProgress>,
-    semaphore: Arc<Semaphore>,
     op: Operator,
-) -> miette::Result<()> {
+    ctx: MirrorContext,
+) -> miette::Result<Vec<String>> {
     let mut tasks = FuturesUnordered::new();
+    let mut packages_deleted = Vec::new();
     if !packages_to_delete.is_empty() {
-        let pb = Arc::new(progress.add(ProgressBar::new(packages_to_delete.len() as u64)));
+        let pb = Arc::new(
+            ctx.progress
+                .add(ProgressBar::new(packages_to_delete.len() as u64)),
+        );
         let sty = ProgressStyle::with_template(
             "[{elapsed_precise}] {bar:40.red/blue} {pos:>7}/{len:7} {msg}",
         )
@@ -229,46 +1280,83 @@ async fn dispatch_tasks_delete(
         .progress_chars("##-");
         pb.set_style(sty);
         let packages_to_delete_len = packages_to_delete.len();
+        let subdir_name = format!("{}/{}", ctx.config.source_display_name(), subdir.as_str());
 
         let pb = pb.clone();
         for filename in packages_to_delete {
             let pb = pb.clone();
-            let semaphore = semaphore.clone();
             let op = op.clone();
+            let ctx = ctx.clone();
+            let subdir_name = subdir_name.clone();
+            #[cfg(feature = "opentelemetry")]
+            let span = tracing::info_span!("delete_package", %filename);
             let task = async move {
-                let _permit = semaphore
+                let _permit = ctx
+                    .semaphore
                     .acquire()
                     .await
                     .expect("Semaphore was unexpectedly closed");
+                if ctx.cancellation_token.is_cancelled() {
+                    return Ok(None);
+                }
+                let _active_task = ActiveTask::new(&ctx.counters);
+                let start = Instant::now();
                 pb.set_message(format!(
                     "Deleting packages in {} {}",
-                    subdir.as_str(),
+                    subdir_name,
                     console::style(&filename).dim()
                 ));
 
                 let destination_path = format!("{}/{}", subdir.as_str(), filename);
+                if ctx.config.soft_delete {
+                    move_to_trash(&op, &ctx.config, subdir, &filename).await?;
+                }
                 op.delete(destination_path.as_str())
                     .await
                     .into_diagnostic()?;
+                if ctx.signer.is_some() {
+                    op.delete(format!("{destination_path}.sig").as_str())
+                        .await
+                        .into_diagnostic()?;
+                }
+                if ctx.config.write_sha256_sidecar {
+                    op.delete(format!("{destination_path}.sha256").as_str())
+                        .await
+                        .into_diagnostic()?;
+                }
+                ctx.counters
+                    .packages_deleted
+                    .fetch_add(1, Ordering::Relaxed);
+                if ctx.config.output_tsv {
+                    print_tsv_row(
+                        PackageAction::Delete,
+                        subdir,
+                        &filename,
+                        None,
+                        start.elapsed(),
+                    );
+                }
 
                 pb.inc(1);
-                let res: miette::Result<()> = Ok(());
+                let res: miette::Result<Option<String>> = Ok(Some(filename));
                 res
             };
+            #[cfg(feature = "opentelemetry")]
+            let task = task.instrument(span);
             tasks.push(tokio::spawn(task));
         }
 
-        let mut results = Vec::new();
         while let Some(join_result) = tasks.next().await {
             match join_result {
-                Ok(Ok(result)) => results.push(result),
+                Ok(Ok(Some(filename))) => packages_deleted.push(filename),
+                Ok(Ok(None)) => {}
                 Ok(Err(e)) => {
                     tasks.clear();
                     tracing::error!("Failed to delete package: {}", e);
                     pb.abandon_with_message(format!(
                         "{} {}",
                         console::style("Failed to delete packages in").red(),
-                        console::style(subdir.as_str()).dim()
+                        console::style(&subdir_name).dim()
                     ));
                     return Err(e);
                 }
@@ -278,7 +1366,7 @@ async fn dispatch_tasks_delete(
                     pb.abandon_with_message(format!(
                         "{} {}",
                         console::style("Failed to delete packages in").red(),
-                        console::style(subdir.as_str()).dim()
+                        console::style(&subdir_name).dim()
                     ));
                     return Err(miette::miette!("Task panicked: {}", join_err));
                 }
@@ -292,227 +1380,1088 @@ async fn dispatch_tasks_delete(
         pb.finish_with_message(format!(
             "{} {}",
             console::style("Finished deleting packages in").green(),
-            subdir.as_str()
+            subdir_name
         ));
     }
+    Ok(packages_deleted)
+}
+
+/// Outcome of adding the packages of a subdir to one destination.
+#[derive(Default)]
+struct DestinationAdds {
+    /// Filenames of the packages that were added.
+    added: Vec<String>,
+    /// The first error while adding packages, the destination is skipped after it.
+    error: Option<miette::Report>,
+}
+
+/// Fails if the filesystem of the local destination at `root` has less free space than
+/// `MIN_FREE_SPACE_FACTOR` times `required_bytes`.
+fn check_free_space(root: &Path, required_bytes: u64) -> miette::Result<()> {
+    let available_bytes = fs4::available_space(root).map_err(|e| {
+        miette::miette!("Could not get the free space of {}: {}", root.display(), e)
+    })?;
+    let needed_bytes = (required_bytes as f64 * MIN_FREE_SPACE_FACTOR) as u64;
+    if available_bytes < needed_bytes {
+        return Err(miette::miette!(
+            "Not enough free space in {}: the packages to add need {} (including a 10% margin), but only {} are available",
+            root.display(),
+            HumanBytes(needed_bytes),
+            HumanBytes(available_bytes)
+        ));
+    }
+    Ok(())
+}
+
+/// Prints the total size of the packages to add according to the source repodata and checks that
+/// every local destination has enough free space for its packages, see `check_free_space`.
+///
+/// A package that is added to several destinations is only downloaded once and counted once in
+/// the estimate. If a destination doesn't have enough space, its plans are replaced by the error,
+/// or it is returned if there is only one destination.
+fn check_disk_space(
+    config: &CondaMirrorConfig,
+    destinations: &[MirrorDestination],
+    all_subdir_plans: &mut [SubdirPlans],
+) -> miette::Result<()> {
+    if config.delete_only {
+        return Ok(());
+    }
+    let mut estimated_bytes = 0;
+    let mut required_bytes = vec![0; destinations.len()];
+    for subdir_plans in all_subdir_plans.iter() {
+        let mut counted = HashSet::new();
+        for (index, plan) in subdir_plans.plans.iter().enumerate() {
+            let Ok(plan) = plan else {
+                continue;
+            };
+            for (filename, package) in &plan.packages_to_add {
+                let size = package.size.unwrap_or_default();
+                required_bytes[index] += size;
+                if counted.insert(filename) {
+                    estimated_bytes += size;
+                }
+            }
+        }
+    }
+    if !config.quiet {
+        eprintln!("Estimated download: {}", HumanBytes(estimated_bytes));
+    }
+
+    for (index, destination) in destinations.iter().enumerate() {
+        let Some(local_root) = &destination.local_root else {
+            continue;
+        };
+        match check_free_space(local_root, required_bytes[index]) {
+            Err(e) if destinations.len() > 1 => {
+                tracing::error!(
+                    "Not mirroring to {}: {}",
+                    destination.ctx.config.destination,
+                    e
+                );
+                for subdir_plans in all_subdir_plans.iter_mut() {
+                    subdir_plans.plans[index] = Err(miette::miette!("{}", e));
+                }
+            }
+            result => result?,
+        }
+    }
     Ok(())
 }
 
+/// Copies the given packages to `subdir` of the destinations with the given indices. Every
+/// package is downloaded once and written to all of its destinations concurrently.
+///
+/// Returns what was added to each destination, in the order of `destinations`, and how many bytes
+/// were downloaded. Failures to download a package are returned as an error, failures to write
+/// it only affect the destination.
 #[allow(clippy::type_complexity)]
+#[cfg_attr(
+    feature = "opentelemetry",
+    tracing::instrument(skip_all, fields(%subdir, packages = packages_to_add.len()))
+)]
 async fn dispatch_tasks_add(
-    packages_to_add: HashMap<String, PackageRecord>,
+    packages_to_add: HashMap<String, (PackageRecord, Url, Vec<usize>)>,
     subdir: Platform,
-    config: CondaMirrorConfig,
-    client: ClientWithMiddleware,
-    progress: Arc<MultiProgress>,
-    semaphore: Arc<Semaphore>,
-    op: Operator,
-) -> miette::Result<()> {
+    destinations: &[MirrorDestination],
+    ctx: MirrorContext,
+) -> miette::Result<(Vec<DestinationAdds>, u64)> {
+    let mut destination_adds = destinations
+        .iter()
+        .map(|_| DestinationAdds::default())
+        .collect::<Vec<_>>();
+    let mut bytes_downloaded = 0;
+    // Filename, size and duration of every package that was downloaded from the source.
+    let mut downloads = Vec::new();
     if !packages_to_add.is_empty() {
         let mut tasks = FuturesUnordered::new();
+        // Destinations that failed, no more packages are written to them.
+        let failed = Arc::new(
+            destinations
+                .iter()
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>(),
+        );
 
-        let pb = Arc::new(progress.add(ProgressBar::new(packages_to_add.len() as u64)));
+        let pb = Arc::new(
+            ctx.progress
+                .add(ProgressBar::new(packages_to_add.len() as u64)),
+        );
+        // Bytes downloaded for this subdir, shown as the average download rate next to the bar.
+        let subdir_bytes = Arc::new(AtomicU64::new(0));
         let sty = ProgressStyle::with_template(
-            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {download_rate:>12} {msg}",
         )
         .unwrap()
+        .with_key("download_rate", {
+            let subdir_bytes = subdir_bytes.clone();
+            move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let secs = state.elapsed().as_secs_f64();
+                let bytes = subdir_bytes.load(Ordering::Relaxed);
+                let rate = if secs > 0.0 { bytes as f64 / secs } else { 0.0 };
+                let _ = write!(w, "{}/s", HumanBytes(rate as u64));
+            }
+        })
         .progress_chars("##-");
         pb.set_style(sty);
         let packages_to_add_len = packages_to_add.len();
+        let subdir_name = format!("{}/{}", ctx.config.source_display_name(), subdir.as_str());
 
         let pb = pb.clone();
-        for (filename, package_record) in packages_to_add {
+        for (filename, (package_record, package_url, indices)) in packages_to_add {
             let pb = pb.clone();
-            let semaphore = semaphore.clone();
-            let config = config.clone();
-            let client = client.clone();
-            let op = op.clone();
+            let ctx = ctx.clone();
+            let targets = indices
+                .into_iter()
+                .map(|index| (index, destinations[index].clone()))
+                .collect::<Vec<_>>();
+            let failed = failed.clone();
+            let subdir_name = subdir_name.clone();
+            let subdir_bytes = subdir_bytes.clone();
+            #[cfg(feature = "opentelemetry")]
+            let span = tracing::info_span!("add_package", %filename);
             let task = async move {
-                let _permit = semaphore
+                let _permit = ctx
+                    .semaphore
                     .acquire()
                     .await
                     .expect("Semaphore was unexpectedly closed");
+                if ctx.cancellation_token.is_cancelled() {
+                    return Ok(None);
+                }
+                let targets = targets
+                    .into_iter()
+                    .filter(|(index, _)| !failed[*index].load(Ordering::Relaxed))
+                    .collect::<Vec<_>>();
+                if targets.is_empty() {
+                    return Ok(None);
+                }
+                let _active_task = ActiveTask::new(&ctx.counters);
+                let start = Instant::now();
                 pb.set_message(format!(
                     "Mirroring {} {}",
-                    subdir.as_str(),
+                    subdir_name,
                     console::style(&filename).dim()
                 ));
 
-                // use rattler client for downloading the package
-                let package_url = config.package_url(filename.as_str(), subdir)?;
-                let mut buf = Vec::new();
-                if package_url.scheme() == "file" {
-                    let path = package_url.to_file_path().unwrap();
-                    let mut file = tokio::fs::File::open(path).await.into_diagnostic()?;
-                    file.read_to_end(&mut buf).await.into_diagnostic()?;
+                let destination_path = format!("{}/{}", subdir.as_str(), filename);
+                // Packages that are copied server-side don't have to be downloaded.
+                let mut copied = Vec::new();
+                let mut uploads = Vec::new();
+                for (index, target) in targets {
+                    let is_copied = match &target.ctx.s3_copy {
+                        Some(s3_copy) => s3_copy.copy(&package_url, &destination_path).await,
+                        None => false,
+                    };
+                    if is_copied {
+                        copied.push((index, target, None));
+                    } else {
+                        uploads.push((index, target));
+                    }
+                }
+                let download = if uploads.is_empty() {
+                    None
                 } else {
-                    let response = client.get(package_url).send().await.into_diagnostic()?;
-                    let bytes = response.bytes().await.into_diagnostic()?;
-                    buf.extend_from_slice(&bytes);
+                    Some(download_package(&ctx, &filename, &package_record, package_url).await?)
                 };
-                tracing::debug!("Downloaded package {} with {} bytes", filename, buf.len());
+                let bytes_downloaded = download.as_ref().map_or(0, |(buf, _)| buf.len() as u64);
+                subdir_bytes.fetch_add(bytes_downloaded, Ordering::Relaxed);
 
-                let expected_digest = package_record.sha256;
-                if let Some(expected_digest) = expected_digest {
-                    let digest: Sha256Hash = compute_bytes_digest::<sha2::Sha256>(&buf);
-                    if expected_digest != digest {
-                        return Err(miette::miette!(
-                            "Digest of {} does not match: {:x} != {:x}",
-                            filename,
-                            expected_digest,
-                            digest
-                        ));
+                let writes =
+                    copied
+                        .into_iter()
+                        .chain(uploads.into_iter().map(|(index, target)| {
+                            let buf = download.as_ref().map(|(buf, _)| buf.clone());
+                            (index, target, buf)
+                        }));
+                let results = join_all(writes.map(|(index, target, buf)| {
+                    let destination_path = &destination_path;
+                    let filename = &filename;
+                    let package_record = &package_record;
+                    let failed = &failed;
+                    async move {
+                        let result =
+                            write_package(&target, destination_path, filename, package_record, buf)
+                                .await;
+                        if let Err(e) = &result {
+                            failed[index].store(true, Ordering::Relaxed);
+                            tracing::error!(
+                                "Failed to add package {} to {}: {}",
+                                filename,
+                                target.ctx.config.destination,
+                                e
+                            );
+                        }
+                        (index, result)
+                    }
+                }))
+                .await;
+                // A package written to several destinations is still added only once.
+                if results.iter().any(|(_, result)| result.is_ok()) {
+                    ctx.counters.packages_added.fetch_add(1, Ordering::Relaxed);
+                    if ctx.config.output_tsv {
+                        print_tsv_row(
+                            PackageAction::Add,
+                            subdir,
+                            &filename,
+                            Some(&package_record),
+                            start.elapsed(),
+                        );
                     }
                 }
-                tracing::debug!("Verified SHA256 of {}", filename);
-
-                // use opendal to upload the package
-                let destination_path = format!("{}/{}", subdir.as_str(), filename);
-                op.write(destination_path.as_str(), buf)
-                    .await
-                    .into_diagnostic()?;
 
                 pb.inc(1);
-                let res: miette::Result<()> = Ok(());
+                let download = download.map(|(buf, duration)| (buf.len() as u64, duration));
+                let res: miette::Result<Option<(String, Option<(u64, Duration)>, _)>> =
+                    Ok(Some((filename, download, results)));
                 res
             };
+            #[cfg(feature = "opentelemetry")]
+            let task = task.instrument(span);
             tasks.push(tokio::spawn(task));
         }
 
-        let mut results = Vec::new();
         while let Some(join_result) = tasks.next().await {
-            match join_result {
-                Ok(Ok(result)) => results.push(result),
+            let error = match join_result {
+                Ok(Ok(Some((filename, download, results)))) => {
+                    if let Some((bytes, duration)) = download {
+                        bytes_downloaded += bytes;
+                        downloads.push((filename.clone(), bytes, duration));
+                    }
+                    for (index, result) in results {
+                        match result {
+                            Ok(()) => destination_adds[index].added.push(filename.clone()),
+                            Err(e) => {
+                                destination_adds[index].error.get_or_insert(e);
+                            }
+                        }
+                    }
+                    // Stop early if no destination can be written to anymore.
+                    if !destination_adds.iter().all(|adds| adds.error.is_some()) {
+                        continue;
+                    }
+                    None
+                }
+                Ok(Ok(None)) => continue,
                 Ok(Err(e)) => {
-                    tasks.clear();
                     tracing::error!("Failed to add package: {}", e);
-                    pb.abandon_with_message(format!(
-                        "{} {}",
-                        console::style("Failed to add packages in").red(),
-                        console::style(subdir.as_str()).dim()
-                    ));
-                    return Err(e);
+                    Some(e)
                 }
                 Err(join_err) => {
-                    tasks.clear();
                     tracing::error!("Task panicked: {}", join_err);
-                    pb.abandon_with_message(format!(
-                        "{} {}",
-                        console::style("Failed to add packages in").red(),
-                        console::style(subdir.as_str()).dim()
-                    ));
-                    return Err(miette::miette!("Task add: {}", join_err));
+                    Some(miette::miette!("Task add: {}", join_err))
                 }
+            };
+            tasks.clear();
+            pb.abandon_with_message(format!(
+                "{} {}",
+                console::style("Failed to add packages in").red(),
+                console::style(&subdir_name).dim()
+            ));
+            match error {
+                Some(e) => return Err(e),
+                None => return Ok((destination_adds, bytes_downloaded)),
             }
         }
-        tracing::debug!(
-            "Successfully added {} packages in subdir {}",
+        tracing::info!(
+            "Successfully added {} packages in subdir {}{}",
             packages_to_add_len,
-            subdir.as_str()
+            subdir.as_str(),
+            format_slowest_downloads(downloads)
         );
-        pb.finish_with_message(format!(
-            "{} {}",
-            console::style("Finished adding packages in").green(),
-            subdir.as_str()
-        ));
+        if destination_adds.iter().any(|adds| adds.error.is_some()) {
+            pb.abandon_with_message(format!(
+                "{} {}",
+                console::style("Failed to add packages to some destinations in").red(),
+                console::style(&subdir_name).dim()
+            ));
+        } else {
+            pb.finish_with_message(format!(
+                "{} {}",
+                console::style("Finished adding packages in").green(),
+                subdir_name
+            ));
+        }
+    }
+    Ok((destination_adds, bytes_downloaded))
+}
+
+/// Lists the slowest of the given downloads by bytes per second, to be appended to a log message.
+fn format_slowest_downloads(mut downloads: Vec<(String, u64, Duration)>) -> String {
+    if downloads.is_empty() {
+        return String::new();
+    }
+    let bytes_per_sec = |(_, bytes, duration): &(String, u64, Duration)| {
+        *bytes as f64 / duration.as_secs_f64().max(f64::EPSILON)
+    };
+    downloads.sort_by(|a, b| bytes_per_sec(a).total_cmp(&bytes_per_sec(b)));
+    let slowest = downloads
+        .iter()
+        .take(SLOWEST_DOWNLOADS_REPORTED)
+        .map(|(filename, bytes, duration)| {
+            format!("{} ({} in {:.1?})", filename, HumanBytes(*bytes), duration)
+        })
+        .collect::<Vec<_>>();
+    format!(", slowest downloads: {}", slowest.join(", "))
+}
+
+/// Downloads a package from the source and verifies its SHA256.
+///
+/// Returns the package and how long the download took.
+async fn download_package(
+    ctx: &MirrorContext,
+    filename: &str,
+    package_record: &PackageRecord,
+    package_url: Url,
+) -> miette::Result<(Buffer, Duration)> {
+    // use rattler client for downloading the package
+    let download_start = Instant::now();
+    let mut buf = Vec::new();
+    if package_url.scheme() == "file" {
+        let path = package_url.to_file_path().unwrap();
+        let mut file = tokio::fs::File::open(path).await.into_diagnostic()?;
+        file.read_to_end(&mut buf).await.into_diagnostic()?;
+        if let Some(limiter) = &ctx.bandwidth_limiter {
+            limiter.acquire(buf.len()).await;
+        }
+    } else {
+        let mut request = ctx.client.get(package_url);
+        if let Some(timeout) = package_timeout(&ctx.config, package_record) {
+            request = request.timeout(timeout);
+        }
+        let mut response = request.send().await.into_diagnostic()?;
+        // Read the body in chunks so that the bandwidth limit throttles the download itself.
+        while let Some(chunk) = response.chunk().await.into_diagnostic()? {
+            if let Some(limiter) = &ctx.bandwidth_limiter {
+                limiter.acquire(chunk.len()).await;
+            }
+            buf.extend_from_slice(&chunk);
+        }
+    };
+    let download_duration = download_start.elapsed();
+    tracing::debug!("Downloaded package {} with {} bytes", filename, buf.len());
+    ctx.counters
+        .bytes_downloaded
+        .fetch_add(buf.len() as u64, Ordering::Relaxed);
+
+    let expected_digest = package_record.sha256;
+    if let Some(expected_digest) = expected_digest {
+        let digest: Sha256Hash = compute_bytes_digest::<sha2::Sha256>(&buf);
+        if expected_digest != digest {
+            return Err(miette::miette!(
+                "Digest of {} does not match: {:x} != {:x}",
+                filename,
+                expected_digest,
+                digest
+            ));
+        }
+    }
+    tracing::debug!("Verified SHA256 of {}", filename);
+    Ok((Buffer::from(buf), download_duration))
+}
+
+/// Writes a downloaded package to `destination_path` of the destination, together with its
+/// signature and checksum file. `buf` is `None` if the package was already copied server-side.
+async fn write_package(
+    destination: &MirrorDestination,
+    destination_path: &str,
+    filename: &str,
+    package_record: &PackageRecord,
+    buf: Option<Buffer>,
+) -> miette::Result<()> {
+    let ctx = &destination.ctx;
+    let op = &destination.op;
+    if let Some(buf) = buf {
+        // use opendal to upload the package
+        op.write(destination_path, buf).await.into_diagnostic()?;
+    }
+    if let Some(signer) = &ctx.signer {
+        let signature_path = format!("{destination_path}.sig");
+        op.write(signature_path.as_str(), signer.sign(package_record)?)
+            .await
+            .into_diagnostic()?;
+        tracing::debug!("Signed {}", filename);
+    }
+    if ctx.config.write_sha256_sidecar {
+        match package_record.sha256 {
+            Some(sha256) => {
+                // Same format as `sha256sum`, so `sha256sum -c` can verify it.
+                let checksum_path = format!("{destination_path}.sha256");
+                op.write(checksum_path.as_str(), format!("{sha256:x}  {filename}\n"))
+                    .await
+                    .into_diagnostic()?;
+            }
+            None => tracing::warn!(
+                "Not writing a checksum file for {}, it has no SHA256 in the repodata",
+                filename
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Timeout of the whole download of a package, scaled with its size if a minimum download speed
+/// is configured. Packages without a size use the timeouts of the client.
+fn package_timeout(config: &CondaMirrorConfig, package_record: &PackageRecord) -> Option<Duration> {
+    let bytes_per_ms = config.bytes_per_ms_timeout?;
+    let size = package_record.size?;
+    let base_timeout = Duration::from_secs(
+        config
+            .read_timeout_secs
+            .unwrap_or(DEFAULT_READ_TIMEOUT_SECS),
+    );
+    let transfer_timeout =
+        Duration::try_from_secs_f64(size as f64 / bytes_per_ms / 1000.0).unwrap_or(Duration::MAX);
+    Some(base_timeout.saturating_add(transfer_timeout))
+}
+
+/// Canonicalizes a local destination path, creating the directory first if it doesn't exist.
+fn canonicalize_or_create(path: &Path) -> miette::Result<PathBuf> {
+    match path.canonicalize() {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::info!("Creating destination directory {}", path.display());
+            std::fs::create_dir_all(path).map_err(|e| {
+                miette::miette!("Could not create directory {}: {}", path.display(), e)
+            })?;
+            path.canonicalize()
+        }
+        result => result,
+    }
+    .map_err(|e| miette::miette!("Could not canonicalize path {}: {}", path.display(), e))
+}
+
+/// Creates an [`Operator`] for the given OpenDAL configuration that retries failed operations.
+pub fn create_operator<T: Configurator>(opendal_config: T) -> miette::Result<Operator> {
+    create_operator_with_layers(opendal_config, RetryLayer::new(), None)
+}
+
+fn create_operator_with_layers<T: Configurator>(
+    opendal_config: T,
+    retry_layer: RetryLayer,
+    circuit_breaker: Option<CircuitBreakerLayer>,
+) -> miette::Result<Operator> {
+    let builder = opendal_config.into_builder();
+    let mut op = Operator::new(builder).into_diagnostic()?.finish();
+    // The circuit breaker sits below the retry layer so that it sees every attempt.
+    if let Some(circuit_breaker) = circuit_breaker {
+        op = op.layer(circuit_breaker);
+    }
+    Ok(op.layer(retry_layer))
+}
+
+/// Returns the circuit breaker layer for OpenDAL operators if it is enabled.
+fn opendal_circuit_breaker_layer(config: &CondaMirrorConfig) -> Option<CircuitBreakerLayer> {
+    config.opendal_circuit_breaker.then(|| {
+        CircuitBreakerLayer::new(
+            config
+                .circuit_breaker_threshold
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_THRESHOLD),
+            Duration::from_secs(
+                config
+                    .circuit_breaker_cooldown_secs
+                    .unwrap_or(DEFAULT_CIRCUIT_BREAKER_COOLDOWN_SECS),
+            ),
+        )
+    })
+}
+
+/// Returns the retry layer for OpenDAL operators, which uses the same retry settings as the HTTP
+/// client.
+fn opendal_retry_layer(config: &CondaMirrorConfig) -> RetryLayer {
+    let mut retry_layer = RetryLayer::new();
+    if let Some(max_retries) = config.retry_max_retries {
+        retry_layer = retry_layer.with_max_times(max_retries as usize);
+    }
+    match config.retry_delay_override_secs {
+        Some(retry_delay_secs) => {
+            let retry_delay = Duration::from_secs(retry_delay_secs);
+            retry_layer = retry_layer
+                .with_min_delay(retry_delay)
+                .with_max_delay(retry_delay)
+                .with_factor(1.0);
+        }
+        None => {
+            if let Some(initial_interval_ms) = config.retry_initial_interval_ms {
+                retry_layer =
+                    retry_layer.with_min_delay(Duration::from_millis(initial_interval_ms));
+            }
+            if let Some(multiplier) = config.retry_multiplier {
+                retry_layer = retry_layer.with_factor(multiplier as f32);
+            }
+        }
     }
-    Ok(())
+    retry_layer
 }
 
-async fn mirror_subdir<T: Configurator>(
+/// Mirrors a single subdir of the source channel to the destination described by
+/// `opendal_config`.
+///
+/// This fetches the repodata of `subdir` from the source, deletes the packages from the
+/// destination that are not selected for mirroring anymore, copies the missing packages and
+/// finally writes the new repodata. Packages that already exist in the destination are not
+/// copied again. Progress bars are added to `progress` and at most as many packages as
+/// `semaphore` has permits are transferred at the same time.
+///
+/// If `cancellation_token` is cancelled, no new packages are transferred and the repodata is not
+/// written. In that case `subdir` is not part of [`MirrorStats::subdirs_mirrored`].
+pub async fn mirror_subdir<T: Configurator>(
     config: CondaMirrorConfig,
     opendal_config: T,
     client: ClientWithMiddleware,
     subdir: Platform,
     progress: Arc<MultiProgress>,
     semaphore: Arc<Semaphore>,
-) -> miette::Result<()> {
-    let repodata_url = config.repodata_url(subdir)?;
-    let repodata = if repodata_url.scheme() == "file" {
-        RepoData::from_path(
-            repodata_url
-                .to_file_path()
-                .map_err(|_| miette::miette!("Invalid file path: {}", repodata_url))?,
-        )
-        .into_diagnostic()?
-    } else {
-        let response = client.get(repodata_url).send().await.into_diagnostic()?;
-        if !response.status().is_success() {
-            return Err(miette::miette!(
-                "Failed to fetch repodata: {}",
-                response.status()
-            ));
+    cancellation_token: CancellationToken,
+) -> miette::Result<MirrorStats> {
+    let op = create_operator_with_layers(
+        opendal_config,
+        opendal_retry_layer(&config),
+        opendal_circuit_breaker_layer(&config),
+    )?;
+    let source_subdirs = config
+        .sources()
+        .map(|source| (source.clone(), vec![subdir]))
+        .collect();
+    let ctx = MirrorContext::new(
+        config,
+        client,
+        source_subdirs,
+        progress,
+        semaphore,
+        cancellation_token,
+    )?;
+    let start = Instant::now();
+    let destination = MirrorDestination {
+        ctx: ctx.clone(),
+        op,
+        local_root: None,
+    };
+    let destinations = std::slice::from_ref(&destination);
+    let subdir_plans = plan_subdir_destinations(destinations, subdir).await?;
+    let (completed, subdir_diff) = mirror_subdir_to_destinations(destinations, subdir_plans)
+        .await?
+        .remove(0)?;
+    let mut stats = ctx.counters.to_stats();
+    if completed {
+        stats.subdirs_mirrored.push(subdir);
+    }
+    stats.subdir_diffs.push(subdir_diff);
+    stats.duration = start.elapsed();
+    Ok(stats)
+}
+
+/// Selects the packages to mirror into `subdir` from the repodata of the sources and applies the
+/// checksum check and name transform to them.
+async fn select_packages(
+    ctx: &MirrorContext,
+    subdir: Platform,
+    available_packages: &HashSet<String>,
+    source_repodata: &[(RepoData, CondaMirrorConfig)],
+) -> miette::Result<(RepoData, HashMap<String, (PackageRecord, Url)>)> {
+    let config = &ctx.config;
+    let (repodata, mut packages_to_mirror) =
+        merge_packages_to_mirror(config, subdir, available_packages, source_repodata)?;
+    check_missing_checksums(
+        subdir,
+        &packages_to_mirror,
+        config.fail_on_missing_checksums,
+    )?;
+    if let Some(command) = &config.package_name_transform_command {
+        packages_to_mirror = transform_package_names(command, packages_to_mirror).await?;
+    }
+    Ok((repodata, packages_to_mirror))
+}
+
+/// Fetches the repodata of all sources that provide `subdir`, together with the configuration of
+/// the source it was fetched from.
+async fn fetch_source_repodata(
+    ctx: &MirrorContext,
+    subdir: Platform,
+) -> miette::Result<Vec<(RepoData, CondaMirrorConfig)>> {
+    let config = &ctx.config;
+    let mut source_repodata = Vec::new();
+    for (source, source_subdirs) in ctx.source_subdirs.iter() {
+        if !source_subdirs.contains(&subdir) {
+            continue;
         }
-        let text = response.text().await.into_diagnostic()?;
-        tracing::debug!("repodata response body: {}", text);
-        serde_json::from_str(&text).into_diagnostic()?
+        let preloaded = ctx
+            .preloaded_repodata
+            .lock()
+            .unwrap()
+            .remove(&(source.to_string(), subdir));
+        let (repodata, source_config) = match preloaded {
+            Some(preloaded) => preloaded,
+            None => {
+                let client = &ctx.client;
+                let (repodata, source_config) =
+                    with_fallback_sources(&config.for_source(source), |source_config| async move {
+                        fetch_repodata(&source_config, client, subdir).await
+                    })
+                    .await?;
+                tracing::info!(
+                    "Fetched repo data for subdir {} from {}",
+                    subdir,
+                    source_config.source
+                );
+                (repodata, source_config)
+            }
+        };
+        source_repodata.push((repodata, source_config));
+    }
+    Ok(source_repodata)
+}
+
+/// Merges the packages to mirror from the repodata of the sources.
+///
+/// Returns the repodata of the first source without its packages, which is used for the channel
+/// info of the written repodata, and the packages to mirror together with the URL they are
+/// downloaded from.
+#[allow(clippy::type_complexity)]
+fn merge_packages_to_mirror(
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+    available_packages: &HashSet<String>,
+    source_repodata: &[(RepoData, CondaMirrorConfig)],
+) -> miette::Result<(RepoData, HashMap<String, (PackageRecord, Url)>)> {
+    let mut packages_to_mirror: HashMap<String, (PackageRecord, Url)> = HashMap::new();
+    for (repodata, source_config) in source_repodata {
+        for (filename, package) in
+            get_packages_to_mirror(repodata, config, subdir, available_packages)
+        {
+            if let Some((existing, existing_url)) = packages_to_mirror.get(&filename) {
+                if existing.sha256 == package.sha256 {
+                    continue;
+                }
+                match config.conflict_resolution {
+                    ConflictResolution::FirstWins => continue,
+                    ConflictResolution::LastWins => {}
+                    ConflictResolution::Error => {
+                        return Err(miette::miette!(
+                            help = "set `conflict-resolution` to `first-wins` or `last-wins` to pick one of them",
+                            "{} differs between {} and {}",
+                            filename,
+                            existing_url,
+                            source_config.package_url(&filename, subdir)?
+                        ));
+                    }
+                }
+            }
+            let package_url = source_config.package_url(&filename, subdir)?;
+            packages_to_mirror.insert(filename, (package, package_url));
+        }
+    }
+    let (first_repodata, _) = source_repodata
+        .first()
+        .ok_or(miette::miette!("No source provides subdir {}", subdir))?;
+    let repodata = RepoData {
+        info: first_repodata.info.clone(),
+        packages: Default::default(),
+        conda_packages: Default::default(),
+        removed: first_repodata.removed.clone(),
+        version: first_repodata.version,
     };
-    tracing::info!("Fetched repo data for subdir: {}", subdir);
+    Ok((repodata, packages_to_mirror))
+}
 
-    let builder = opendal_config.into_builder();
-    let op = Operator::new(builder)
-        .into_diagnostic()?
-        .layer(RetryLayer::new())
-        .finish();
-    let available_packages = op
-        .list_with(&format!("{}/", subdir.as_str()))
-        .await
-        .into_diagnostic()?
-        .iter()
-        .filter_map(|entry| {
-            if entry.metadata().mode().is_file() {
-                let filename = entry.name().to_string();
-                ArchiveType::try_from(&filename).map(|_| filename)
-            } else {
-                None
+/// The plans of a subdir for all destinations, in the order of the destinations.
+struct SubdirPlans {
+    subdir: Platform,
+    plans: Vec<miette::Result<MirrorPlan>>,
+    /// How long fetching the source repodata and planning took.
+    planning_duration: Duration,
+}
+
+/// Fetches the source repodata of `subdir` once and plans the subdir for all destinations.
+///
+/// A destination that can't be planned doesn't affect the others, an error is only returned if
+/// fetching the repodata fails.
+#[cfg_attr(
+    feature = "opentelemetry",
+    tracing::instrument(name = "plan_subdir", parent = None, skip_all, fields(%subdir))
+)]
+async fn plan_subdir_destinations(
+    destinations: &[MirrorDestination],
+    subdir: Platform,
+) -> miette::Result<SubdirPlans> {
+    let start = Instant::now();
+    let ctx = &destinations
+        .first()
+        .ok_or(miette::miette!("No destination to mirror {} to", subdir))?
+        .ctx;
+    let source_repodata = fetch_source_repodata(ctx, subdir).await?;
+    let mut plans = Vec::new();
+    for destination in destinations {
+        plans.push(plan_subdir(&destination.ctx, &destination.op, subdir, &source_repodata).await);
+    }
+    Ok(SubdirPlans {
+        subdir,
+        plans,
+        planning_duration: start.elapsed(),
+    })
+}
+
+/// Executes the plans of a single subdir and returns, for every destination, whether it was
+/// mirrored completely together with the statistics of the subdir.
+///
+/// Every package is downloaded once for all destinations. An error of a destination doesn't
+/// affect the others, an error is only returned if downloading a package fails.
+///
+/// If `cancellation_token` is cancelled while the packages are transferred, the repodata is not
+/// written and `false` is returned.
+// Every subdir is its own trace, so that large mirrors don't end up in a single huge trace.
+#[cfg_attr(
+    feature = "opentelemetry",
+    tracing::instrument(
+        name = "mirror_subdir",
+        parent = None,
+        skip_all,
+        fields(subdir = %subdir_plans.subdir)
+    )
+)]
+async fn mirror_subdir_to_destinations(
+    destinations: &[MirrorDestination],
+    subdir_plans: SubdirPlans,
+) -> miette::Result<Vec<miette::Result<(bool, MirrorSubdirDiff)>>> {
+    let SubdirPlans {
+        subdir,
+        plans,
+        planning_duration,
+    } = subdir_plans;
+    // Planning counts towards the time of the subdir, waiting for the other subdirs doesn't.
+    let start = Instant::now() - planning_duration;
+    let ctx = &destinations
+        .first()
+        .ok_or(miette::miette!("No destination to mirror {} to", subdir))?
+        .ctx;
+    let mut pending = Vec::new();
+    for (destination, plan) in destinations.iter().zip(plans) {
+        let result = match plan {
+            Ok(plan) => start_plan(&destination.ctx, &destination.op, plan).await,
+            Err(e) => Err(e),
+        };
+        pending.push(result);
+    }
+
+    // The packages to add with the indices of the destinations that need them.
+    let mut packages_to_add: HashMap<String, (PackageRecord, Url, Vec<usize>)> = HashMap::new();
+    for (index, pending) in pending.iter_mut().enumerate() {
+        let Ok(pending) = pending else {
+            continue;
+        };
+        for (filename, (package, package_url)) in std::mem::take(&mut pending.packages_to_add) {
+            packages_to_add
+                .entry(filename)
+                .or_insert_with(|| (package, package_url, Vec::new()))
+                .2
+                .push(index);
+        }
+    }
+    tracing::info!("Adding {} packages in {}", packages_to_add.len(), subdir);
+    let (destination_adds, bytes_downloaded) =
+        dispatch_tasks_add(packages_to_add, subdir, destinations, ctx.clone()).await?;
+
+    let mut results = Vec::new();
+    for ((destination, pending), adds) in destinations.iter().zip(pending).zip(destination_adds) {
+        let result = match (pending, adds.error) {
+            (Ok(pending), None) => {
+                finish_plan(
+                    &destination.ctx,
+                    &destination.op,
+                    pending,
+                    adds.added,
+                    bytes_downloaded,
+                    start,
+                )
+                .await
             }
-        })
-        .collect::<HashSet<_>>();
+            (Err(e), _) | (Ok(_), Some(e)) => Err(e),
+        };
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Decides which packages of `subdir` to add to and delete from the destination `op`, without
+/// changing anything.
+pub async fn plan_mirror_subdir(
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+    client: &ClientWithMiddleware,
+    op: &Operator,
+) -> miette::Result<MirrorPlan> {
+    let ctx = subdir_context(config, client, subdir)?;
+    let source_repodata = fetch_source_repodata(&ctx, subdir).await?;
+    plan_subdir(&ctx, op, subdir, &source_repodata).await
+}
 
-    let packages_to_mirror = get_packages_to_mirror(&repodata, &config);
+async fn plan_subdir(
+    ctx: &MirrorContext,
+    op: &Operator,
+    subdir: Platform,
+    source_repodata: &[(RepoData, CondaMirrorConfig)],
+) -> miette::Result<MirrorPlan> {
+    let config = &ctx.config;
+    let available_packages = list_available_packages(op, subdir).await?;
+    let (repodata, packages_to_mirror) =
+        select_packages(ctx, subdir, &available_packages, source_repodata).await?;
+    let diff = RepoDataDiff::new(
+        packages_to_mirror
+            .iter()
+            .map(|(filename, (package, _))| (filename, package)),
+        &available_packages,
+        &read_destination_records(op, subdir).await?,
+    );
     tracing::info!(
         "Mirroring {} packages in {}",
         packages_to_mirror.len(),
         subdir,
     );
-    let packages_to_delete = available_packages
-        .difference(&packages_to_mirror.keys().cloned().collect::<HashSet<_>>())
-        .cloned()
-        .collect::<Vec<_>>();
-    let mut packages_to_add = HashMap::new();
-    for (filename, package) in packages_to_mirror.clone() {
-        if !available_packages.contains(&filename) {
-            packages_to_add.insert(filename, package);
+    if !diff.checksum_mismatch.is_empty() {
+        tracing::warn!(
+            "{} packages in {} changed in the source and are downloaded again",
+            diff.checksum_mismatch.len(),
+            subdir
+        );
+    }
+    let (packages_to_delete, packages_to_preserve) = if config.preserve_removed {
+        // Only files that never made it into the repodata are deleted.
+        let untracked = diff.untracked_in_dest.iter().cloned().collect::<Vec<_>>();
+        (untracked, diff.only_in_dest.clone())
+    } else {
+        (diff.packages_to_delete(), HashMap::new())
+    };
+    let packages_to_add = packages_to_mirror
+        .iter()
+        .filter(|(filename, _)| config.force_redownload || diff.needs_transfer(filename))
+        .map(|(filename, (package, _))| (filename.clone(), package.clone()))
+        .collect::<HashMap<_, _>>();
+
+    if !config.force_delete && !available_packages.is_empty() {
+        let max_delete_fraction = config
+            .max_delete_fraction
+            .unwrap_or(DEFAULT_MAX_DELETE_FRACTION);
+        check_delete_fraction(
+            subdir,
+            packages_to_delete.len(),
+            available_packages.len(),
+            max_delete_fraction,
+        )?;
+    }
+
+    Ok(MirrorPlan {
+        subdir,
+        packages_to_add,
+        packages_to_delete,
+        packages_to_mirror,
+        packages_to_preserve,
+        repodata,
+    })
+}
+
+/// Refuses to delete more than `max_delete_fraction` of the `existing` packages in `subdir`.
+fn check_delete_fraction(
+    subdir: Platform,
+    to_delete: usize,
+    existing: usize,
+    max_delete_fraction: f64,
+) -> miette::Result<()> {
+    let delete_fraction = to_delete as f64 / existing as f64;
+    if delete_fraction > max_delete_fraction {
+        return Err(miette::miette!(
+            help = "Check your include/exclude configuration or use --force-delete to delete them anyway",
+            "Refusing to delete {} of {} existing packages in {} ({:.1}% > {:.1}%)",
+            to_delete,
+            existing,
+            subdir,
+            delete_fraction * 100.0,
+            max_delete_fraction * 100.0
+        ));
+    }
+    Ok(())
+}
+
+/// A subdir of a destination whose packages were deleted and whose packages are about to be added.
+struct PendingSubdir {
+    subdir: Platform,
+    packages_to_add: HashMap<String, (PackageRecord, Url)>,
+    packages_deleted: Vec<String>,
+    packages_skipped: usize,
+    packages_to_mirror: HashMap<String, (PackageRecord, Url)>,
+    packages_to_preserve: HashMap<String, PackageRecord>,
+    repodata: RepoData,
+}
+
+/// Deletes the packages of `plan` from the destination. The packages are added to all
+/// destinations at once, see `mirror_subdir_to_destinations`.
+async fn start_plan(
+    ctx: &MirrorContext,
+    op: &Operator,
+    plan: MirrorPlan,
+) -> miette::Result<PendingSubdir> {
+    let config = &ctx.config;
+    let MirrorPlan {
+        subdir,
+        packages_to_add,
+        mut packages_to_delete,
+        mut packages_to_mirror,
+        packages_to_preserve,
+        repodata,
+    } = plan;
+    let packages_to_add = packages_to_add
+        .into_iter()
+        .map(|(filename, package)| {
+            let (_, package_url) = packages_to_mirror.get(&filename).ok_or(miette::miette!(
+                "Package {} is added but not in the packages to mirror of {}",
+                filename,
+                subdir
+            ))?;
+            Ok((filename, (package, package_url.clone())))
+        })
+        .collect::<miette::Result<HashMap<_, _>>>()?;
+    let packages_skipped = packages_to_mirror
+        .len()
+        .saturating_sub(packages_to_add.len());
+    ctx.counters
+        .packages_skipped
+        .fetch_add(packages_skipped, Ordering::Relaxed);
+
+    if config.output_tsv {
+        for (filename, (package, _)) in &packages_to_mirror {
+            if !packages_to_add.contains_key(filename) {
+                print_tsv_row(
+                    PackageAction::Skip,
+                    subdir,
+                    filename,
+                    Some(package),
+                    Duration::ZERO,
+                );
+            }
         }
     }
 
+    if let (Some(runs), Some(stats_db_path)) = (config.smart_delete_runs, &config.stats_db_path) {
+        let missing = packages_to_delete.len();
+        packages_to_delete = update_absent_packages(
+            stats_db_path,
+            &config.destination.to_string(),
+            subdir,
+            &packages_to_delete,
+            runs,
+        )
+        .await?;
+        tracing::info!(
+            "Keeping {} packages in {} that were missing in the source for less than {} runs",
+            missing - packages_to_delete.len(),
+            subdir,
+            runs
+        );
+    }
+
     tracing::info!(
         "Deleting {} existing packages in {}",
         packages_to_delete.len(),
         subdir
     );
-    dispatch_tasks_delete(
-        packages_to_delete,
-        subdir,
-        progress.clone(),
-        semaphore.clone(),
-        op.clone(),
-    )
-    .await?;
+    let packages_deleted =
+        dispatch_tasks_delete(packages_to_delete, subdir, op.clone(), ctx.clone()).await?;
 
-    tracing::info!("Adding {} packages in {}", packages_to_add.len(), subdir);
-    dispatch_tasks_add(
+    let packages_to_add = if config.delete_only {
+        tracing::info!(
+            "Not adding {} packages in {}, they are left out of the repodata",
+            packages_to_add.len(),
+            subdir
+        );
+        for filename in packages_to_add.keys() {
+            packages_to_mirror.remove(filename);
+        }
+        HashMap::new()
+    } else {
+        packages_to_add
+    };
+    Ok(PendingSubdir {
+        subdir,
         packages_to_add,
+        packages_deleted,
+        packages_skipped,
+        packages_to_mirror,
+        packages_to_preserve,
+        repodata,
+    })
+}
+
+/// Writes the new repodata of a subdir after its packages were added. Returns whether the subdir
+/// was mirrored completely, together with what changed.
+async fn finish_plan(
+    ctx: &MirrorContext,
+    op: &Operator,
+    pending: PendingSubdir,
+    mut packages_added: Vec<String>,
+    bytes_downloaded: u64,
+    start: Instant,
+) -> miette::Result<(bool, MirrorSubdirDiff)> {
+    let config = &ctx.config;
+    let PendingSubdir {
         subdir,
-        config,
-        client,
-        progress.clone(),
-        semaphore.clone(),
-        op.clone(),
-    )
-    .await?;
+        mut packages_deleted,
+        packages_skipped,
+        packages_to_mirror,
+        packages_to_preserve,
+        repodata,
+        ..
+    } = pending;
+    packages_added.sort();
+    packages_deleted.sort();
+    let subdir_diff = move || MirrorSubdirDiff {
+        subdir,
+        destination: config.destination.to_string(),
+        added: packages_added,
+        deleted: packages_deleted,
+        skipped: packages_skipped,
+        bytes_transferred: bytes_downloaded,
+        duration: start.elapsed(),
+    };
+    if ctx.cancellation_token.is_cancelled() {
+        tracing::info!("Mirroring of {} was interrupted", subdir);
+        return Ok((false, subdir_diff()));
+    }
 
     /* ---------------------------- WRITE REPODATA ---------------------------- */
+    let mut packages_to_mirror = packages_to_mirror
+        .into_iter()
+        .map(|(filename, (package, _))| (filename, package))
+        .collect::<HashMap<_, _>>();
+    let mut removed = repodata.removed;
+    if !packages_to_preserve.is_empty() {
+        tracing::info!(
+            "Keeping {} packages in {} that were removed from the source",
+            packages_to_preserve.len(),
+            subdir
+        );
+        for (filename, package) in packages_to_preserve {
+            removed.insert(filename.clone());
+            packages_to_mirror.insert(filename, package);
+        }
+    }
     let packages = packages_to_mirror
         .iter()
         .filter(
@@ -543,26 +2492,189 @@ async fn mirror_subdir<T: Configurator>(
         info: repodata.info,
         packages,
         conda_packages,
-        removed: repodata.removed,
+        removed,
         version: repodata.version,
     };
 
-    write_repodata(new_repodata, None, true, true, subdir, op)
+    let repodata_path = format!("{}/repodata.json", subdir.as_str());
+    let old_repodata = if config.write_jlap && op.exists(&repodata_path).await.into_diagnostic()? {
+        Some(op.read(&repodata_path).await.into_diagnostic()?.to_vec())
+    } else {
+        None
+    };
+
+    write_repodata(new_repodata.clone(), None, true, true, subdir, op.clone())
         .await
         .map_err(|e| miette::miette!("Could not write repodata: {}", e))?;
+    if config.write_jlap {
+        let new_repodata = op.read(&repodata_path).await.into_diagnostic()?.to_vec();
+        write_jlap(op, subdir, old_repodata.as_deref(), &new_repodata).await?;
+    }
+    if config.write_manifest {
+        write_manifest(op, subdir, &new_repodata).await?;
+    }
+    verify_packages_written(op, subdir, packages_to_mirror.keys()).await?;
     // todo: check if non-conda and non-repodata files exist, print warning if any
+    Ok((true, subdir_diff()))
+}
+
+/// Copies a package to `{trash_prefix}{subdir}/{filename}_{timestamp}` before it is deleted.
+async fn move_to_trash(
+    op: &Operator,
+    config: &CondaMirrorConfig,
+    subdir: Platform,
+    filename: &str,
+) -> miette::Result<()> {
+    let trash_prefix = config
+        .trash_prefix
+        .as_deref()
+        .unwrap_or(DEFAULT_TRASH_PREFIX)
+        .trim_end_matches('/');
+    let source_path = format!("{}/{}", subdir.as_str(), filename);
+    let trash_path = format!(
+        "{}/{}/{}_{}",
+        trash_prefix,
+        subdir.as_str(),
+        filename,
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    match op.copy(&source_path, &trash_path).await {
+        Err(e) if e.kind() == opendal::ErrorKind::Unsupported => {
+            let package = op.read(&source_path).await.into_diagnostic()?;
+            op.write(&trash_path, package).await.into_diagnostic()?;
+        }
+        result => result.into_diagnostic()?,
+    }
+    tracing::debug!("Moved {} to {}", source_path, trash_path);
+    Ok(())
+}
+
+/// Warns about packages without a SHA256 in the repodata, whose downloads can't be verified.
+/// With `fail` set, mirroring the subdir fails instead.
+fn check_missing_checksums(
+    subdir: Platform,
+    packages_to_mirror: &HashMap<String, (PackageRecord, Url)>,
+    fail: bool,
+) -> miette::Result<()> {
+    let mut missing = packages_to_mirror
+        .iter()
+        .filter(|(_, (package, _))| package.sha256.is_none())
+        .map(|(filename, _)| filename)
+        .collect::<Vec<_>>();
+    missing.sort();
+    for filename in &missing {
+        tracing::warn!(
+            "Package {}/{} has no SHA256 in the repodata",
+            subdir,
+            filename
+        );
+    }
+    if fail && !missing.is_empty() {
+        return Err(miette::miette!(
+            help = "remove --fail-on-missing-checksums to mirror them without verification",
+            "{} packages in {} have no SHA256 in the repodata",
+            missing.len(),
+            subdir
+        ));
+    }
+    Ok(())
+}
+
+/// Lists the filenames of all packages in `subdir` of the destination.
+async fn list_available_packages(
+    op: &Operator,
+    subdir: Platform,
+) -> miette::Result<HashSet<String>> {
+    Ok(op
+        .list_with(&format!("{}/", subdir.as_str()))
+        .await
+        .into_diagnostic()?
+        .iter()
+        .filter_map(|entry| {
+            if entry.metadata().mode().is_file() {
+                let filename = entry.name().to_string();
+                ArchiveType::try_from(&filename).map(|_| filename)
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+/// Lists the destination again and warns about packages that were written but are missing, which
+/// happens with some object stores that acknowledge writes under high concurrency without
+/// persisting them.
+async fn verify_packages_written(
+    op: &Operator,
+    subdir: Platform,
+    filenames: impl Iterator<Item = &String>,
+) -> miette::Result<()> {
+    let available_packages = list_available_packages(op, subdir).await?;
+    let mut total = 0;
+    let mut missing = filenames
+        .inspect(|_| total += 1)
+        .filter(|filename| !available_packages.contains(*filename))
+        .collect::<Vec<_>>();
+    missing.sort();
+    for filename in &missing {
+        tracing::warn!(
+            "Package {}/{} is in the repodata but missing in the destination",
+            subdir,
+            filename
+        );
+    }
+    if !missing.is_empty() {
+        tracing::warn!(
+            "{} of {} packages in {} are missing in the destination, run the mirror again to add them",
+            missing.len(),
+            total,
+            subdir
+        );
+    }
     Ok(())
 }
 
-async fn get_subdirs(
+/// Lists the subdirs of every source, using its fallback sources if it can't be reached.
+async fn get_source_subdirs(
     config: &CondaMirrorConfig,
-    client: ClientWithMiddleware,
-) -> miette::Result<Vec<Platform>> {
+) -> miette::Result<Vec<(NamedChannelOrUrl, Vec<Platform>)>> {
+    let mut source_subdirs = Vec::new();
+    for source in config.sources() {
+        let (subdirs, _) =
+            with_fallback_sources(&config.for_source(source), |source_config| async move {
+                get_subdirs(&source_config).await
+            })
+            .await?;
+        source_subdirs.push((source.clone(), subdirs));
+    }
+    Ok(source_subdirs)
+}
+
+/// The subdirs of the source that pass `include_subdirs` and `exclude_subdirs`.
+async fn get_subdirs(config: &CondaMirrorConfig) -> miette::Result<Vec<Platform>> {
+    let mut subdirs = detect_subdirs(config).await?;
+    subdirs.retain(|subdir| config.subdir_selected(*subdir));
+    Ok(subdirs)
+}
+
+async fn detect_subdirs(config: &CondaMirrorConfig) -> miette::Result<Vec<Platform>> {
     if let Some(subdirs) = config.subdirs.clone() {
         return Ok(subdirs);
     }
+    if config.source_type == SourceType::PackageCache {
+        let path = package_cache_path(config)?;
+        return tokio::task::spawn_blocking(move || package_cache_subdirs(&path))
+            .await
+            .into_diagnostic()?;
+    }
 
+    let client = get_probe_client(config)?;
     let mut subdirs = Vec::new();
+    let probe_timeout = Duration::from_millis(
+        config
+            .source_probe_timeout_ms
+            .unwrap_or(DEFAULT_SOURCE_PROBE_TIMEOUT_MS),
+    );
 
     for subdir in Platform::all() {
         tracing::debug!("Checking subdir: {}", subdir);
@@ -576,11 +2688,30 @@ async fn get_subdirs(
                 subdirs.push(subdir);
             }
         } else {
-            let response = client
+            let response = match client
                 .head(repodata_url.clone())
+                .timeout(probe_timeout)
                 .send()
                 .await
-                .into_diagnostic()?;
+            {
+                Ok(response) => response,
+                Err(e) if e.is_timeout() => {
+                    tracing::warn!(
+                        "Checking {} timed out after {:?}, skipping subdir {}",
+                        repodata_url,
+                        probe_timeout,
+                        subdir
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    return Err(SourceUnreachable {
+                        url: repodata_url,
+                        reason: e.to_string(),
+                    }
+                    .into());
+                }
+            };
             tracing::debug!("Got response for url {}: {:?}", repodata_url, response);
 
             if response.status().is_success() {
@@ -591,41 +2722,109 @@ async fn get_subdirs(
     Ok(subdirs)
 }
 
+fn package_cache_path(config: &CondaMirrorConfig) -> miette::Result<PathBuf> {
+    config.local_source_path()?.ok_or(miette::miette!(
+        "Package cache {} is not a local directory",
+        config.source
+    ))
+}
+
 fn get_client(config: &CondaMirrorConfig) -> miette::Result<ClientWithMiddleware> {
-    let client = Client::builder()
+    let client_builder = authenticated_client_builder(config)?;
+
+    let retry_policy = match config.retry_delay_override_secs {
+        Some(retry_delay_secs) => {
+            // An exponential backoff with base 1 and no jitter waits the same time before each retry.
+            let retry_delay = Duration::from_secs(retry_delay_secs);
+            ExponentialBackoff::builder()
+                .retry_bounds(retry_delay, retry_delay)
+                .base(1)
+                .jitter(Jitter::None)
+        }
+        None => {
+            let mut retry_policy = ExponentialBackoff::builder();
+            if let Some(initial_interval_ms) = config.retry_initial_interval_ms {
+                let initial_interval = Duration::from_millis(initial_interval_ms);
+                retry_policy = retry_policy
+                    .retry_bounds(initial_interval, initial_interval.max(MAX_RETRY_INTERVAL));
+            }
+            if let Some(multiplier) = config.retry_multiplier {
+                retry_policy = retry_policy.base(multiplier);
+            }
+            retry_policy
+        }
+    };
+    let authenticated_client = client_builder
+        .with(RetryTransientMiddleware::new_with_policy(
+            retry_policy.build_with_max_retries(
+                config.retry_max_retries.unwrap_or(DEFAULT_HTTP_MAX_RETRIES),
+            ),
+        ))
+        .build();
+    Ok(authenticated_client)
+}
+
+/// The client of `get_client` without retries, for the HEAD requests that detect the subdirs of
+/// the source.
+///
+/// A retried timeout would take far longer than `source_probe_timeout_ms`.
+fn get_probe_client(config: &CondaMirrorConfig) -> miette::Result<ClientWithMiddleware> {
+    Ok(authenticated_client_builder(config)?.build())
+}
+
+/// The HTTP client with the timeouts and the authentication middlewares for the source.
+fn authenticated_client_builder(config: &CondaMirrorConfig) -> miette::Result<ClientBuilder> {
+    let mut client = Client::builder()
         .pool_max_idle_per_host(20)
-        .user_agent("conda-mirror")
-        .read_timeout(Duration::from_secs(120))
-        .build()
-        .expect("failed to create reqwest Client");
+        .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+        .read_timeout(Duration::from_secs(
+            config
+                .read_timeout_secs
+                .unwrap_or(DEFAULT_READ_TIMEOUT_SECS),
+        ));
+    if let Some(connect_timeout_secs) = config.connect_timeout_secs {
+        client = client.connect_timeout(Duration::from_secs(connect_timeout_secs));
+    }
+    if let Some(request_timeout_secs) = config.request_timeout_secs {
+        client = client.timeout(Duration::from_secs(request_timeout_secs));
+    }
+    if let Some(max_redirects) = config.source_max_redirects {
+        client = client.redirect(Policy::limited(max_redirects));
+    }
+    match config.use_http2 {
+        Some(true) => client = client.http2_prior_knowledge(),
+        Some(false) => client = client.http1_only(),
+        None => {}
+    }
+    let client = client.build().expect("failed to create reqwest Client");
     let mut client_builder = ClientBuilder::new(client.clone());
 
     let auth_store = AuthenticationStorage::from_env_and_defaults().into_diagnostic()?;
-    if let NamedChannelOrUrl::Url(source_url) = config.source.clone() {
-        if source_url.scheme() == "s3" {
-            let s3_host = source_url
-                .host()
-                .ok_or(miette::miette!("Invalid S3 url: {}", source_url))?
-                .to_string();
-            let s3_config = config
-                .clone()
-                .s3_config_source
-                .ok_or(miette::miette!("No S3 source config set"))?;
-
-            let s3_middleware = S3Middleware::new(
-                HashMap::from([(
-                    s3_host,
-                    S3Config::Custom {
-                        endpoint_url: s3_config.endpoint_url,
-                        region: s3_config.region,
-                        force_path_style: s3_config.force_path_style,
-                    },
-                )]),
-                // TODO: once rattler has a custom InMemoryBackend, add this to auth_store with custom source credentials
-                auth_store,
-            );
-            client_builder = client_builder.with(s3_middleware);
-        }
+    if let NamedChannelOrUrl::Url(source_url) = config.source.clone()
+        && source_url.scheme() == "s3"
+    {
+        let s3_host = source_url
+            .host()
+            .ok_or(miette::miette!("Invalid S3 url: {}", source_url))?
+            .to_string();
+        let s3_config = config
+            .clone()
+            .s3_config_source
+            .ok_or(miette::miette!("No S3 source config set"))?;
+
+        let s3_middleware = S3Middleware::new(
+            HashMap::from([(
+                s3_host,
+                S3Config::Custom {
+                    endpoint_url: s3_config.endpoint_url,
+                    region: s3_config.region,
+                    force_path_style: s3_config.force_path_style,
+                },
+            )]),
+            // TODO: once rattler has a custom InMemoryBackend, add this to auth_store with custom source credentials
+            auth_store,
+        );
+        client_builder = client_builder.with(s3_middleware);
     }
 
     let auth_store = if let Some(s3_credentials) = config.s3_credentials_source.clone() {
@@ -661,6 +2860,24 @@ fn get_client(config: &CondaMirrorConfig) -> miette::Result<ClientWithMiddleware
             .into_diagnostic()?;
         auth_store.backends.insert(0, Arc::new(memory_storage));
         auth_store
+    } else if config.bearer_token.is_none()
+        && config.quetz_token.is_none()
+        && config.conda_token.is_none()
+        && let Some(token) = condarc::token_for_channel(&config.source)?
+    {
+        // Fall back to the token from the user's .condarc if no credentials are configured.
+        let mut auth_store = AuthenticationStorage::from_env_and_defaults().into_diagnostic()?;
+        let source_url = config.repodata_url(Platform::NoArch)?;
+        let host = source_url
+            .host_str()
+            .ok_or(miette::miette!("Invalid source URL: {}", source_url))?;
+        tracing::info!("Using token from .condarc for {}", host);
+        let memory_storage = MemoryStorage::default();
+        memory_storage
+            .store(host, &Authentication::BearerToken(token))
+            .into_diagnostic()?;
+        auth_store.backends.insert(0, Arc::new(memory_storage));
+        auth_store
     } else {
         AuthenticationStorage::from_env_and_defaults().into_diagnostic()?
     };
@@ -669,10 +2886,72 @@ fn get_client(config: &CondaMirrorConfig) -> miette::Result<ClientWithMiddleware
         AuthenticationMiddleware::from_auth_storage(auth_store),
     ));
 
-    client_builder = client_builder.with(RetryTransientMiddleware::new_with_policy(
-        ExponentialBackoff::builder().build_with_max_retries(12),
-    ));
+    if let Some(bearer_token) = config.bearer_token.as_ref() {
+        let source_url = config.repodata_url(Platform::NoArch)?;
+        let host = source_url
+            .host_str()
+            .ok_or(miette::miette!(
+                "Bearer tokens can only be used with HTTP(S) sources, got {}",
+                config.source
+            ))?
+            .to_string();
+        client_builder = client_builder.with(BearerTokenMiddleware::new(host, &bearer_token.0)?);
+    }
 
-    let authenticated_client = client_builder.build();
-    Ok(authenticated_client)
+    if let Some(quetz_token) = config.quetz_token.as_ref() {
+        let source_url = config.repodata_url(Platform::NoArch)?;
+        let host = source_url
+            .host_str()
+            .ok_or(miette::miette!("Invalid source URL: {}", source_url))?
+            .to_string();
+        client_builder = client_builder.with(QuetzTokenMiddleware::new(
+            host,
+            &quetz_token.0,
+            config.quetz_token_location,
+        )?);
+    }
+
+    if let Some(conda_token) = config.conda_token.as_ref() {
+        client_builder = client_builder.with(CondaTokenMiddleware::new(&conda_token.0));
+    }
+
+    Ok(client_builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prerelease_versions() {
+        let is_prerelease = |version: &str| is_prerelease(&version.parse().unwrap());
+        assert!(is_prerelease("1.0a1"));
+        assert!(is_prerelease("2.0rc1"));
+        assert!(is_prerelease("2.0.0rc1"));
+        assert!(is_prerelease("1.0.dev0"));
+        assert!(is_prerelease("1.0b2+local"));
+        assert!(!is_prerelease("2024b"));
+        assert!(!is_prerelease("1.1.1b"));
+        assert!(!is_prerelease("1.0+beta"));
+        assert!(!is_prerelease("1.26.0"));
+    }
+
+    #[test]
+    fn delete_fraction_boundary() {
+        let check = |to_delete, existing| {
+            check_delete_fraction(
+                Platform::Linux64,
+                to_delete,
+                existing,
+                DEFAULT_MAX_DELETE_FRACTION,
+            )
+        };
+        assert!(check(0, 10).is_ok());
+        assert!(check(2, 10).is_ok());
+        assert!(check(20, 100).is_ok());
+        assert!(check(21, 100).is_err());
+        assert!(check(3, 10).is_err());
+        assert!(check(10, 10).is_err());
+        assert!(check_delete_fraction(Platform::Linux64, 10, 10, 1.0).is_ok());
+    }
 }
@@ -0,0 +1,59 @@
+use miette::IntoDiagnostic;
+use opendal::Operator;
+use rattler_conda_types::{Platform, RepoData};
+use std::path::{Path, PathBuf};
+
+/// Writes the paths of all packages in the given subdirs of the destination to `path`, one
+/// `{subdir}/{filename}` per line.
+///
+/// Like `channeldata.json`, the listing is based on the `repodata.json` files in the destination so
+/// it also covers subdirs that were mirrored in an earlier (resumed) run.
+pub(crate) async fn write_package_listing(
+    op: &Operator,
+    subdirs: &[Platform],
+    path: &Path,
+) -> miette::Result<()> {
+    let mut paths = Vec::new();
+    for subdir in subdirs {
+        let repodata_bytes = op
+            .read(&format!("{}/repodata.json", subdir.as_str()))
+            .await
+            .into_diagnostic()?;
+        let repodata: RepoData =
+            serde_json::from_slice(&repodata_bytes.to_vec()).into_diagnostic()?;
+        paths.extend(
+            repodata
+                .packages
+                .keys()
+                .chain(repodata.conda_packages.keys())
+                .map(|filename| format!("{}/{}", subdir.as_str(), filename)),
+        );
+    }
+    paths.sort();
+
+    let mut listing = paths.join("\n");
+    if !listing.is_empty() {
+        listing.push('\n');
+    }
+    std::fs::write(path, listing).map_err(|e| {
+        miette::miette!("Could not write package listing {}: {}", path.display(), e)
+    })?;
+    tracing::info!("Wrote {} package paths to {}", paths.len(), path.display());
+    Ok(())
+}
+
+/// The path of the package listing of the destination at `index` out of `count` destinations.
+///
+/// A single destination uses `path` itself, otherwise the index is appended to the file stem, e.g.
+/// `packages-1.txt` for the second destination.
+pub(crate) fn destination_listing_path(path: &Path, index: usize, count: usize) -> PathBuf {
+    if count == 1 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}-{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{index}"),
+    };
+    path.with_file_name(file_name)
+}
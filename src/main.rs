@@ -1,11 +1,36 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use miette::IntoDiagnostic;
+#[cfg(feature = "opentelemetry")]
+use opentelemetry::trace::TracerProvider;
+use rattler_conda_types::{ChannelConfig, MatchSpec, NamedChannelOrUrl};
+use std::{
+    collections::HashMap,
+    env::current_dir,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{
+    Layer, filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
 use conda_mirror::{
+    check_completeness,
+    completeness::format_completeness_report,
     config::{
-        CliConfig, CondaMirrorConfig, CondaMirrorYamlConfig, MirrorMode, S3Config, S3Credentials,
+        B2Credentials, CliConfig, Command, CondaMirrorConfig, CondaMirrorYamlConfig,
+        EXAMPLE_CONFIG, LogFormat, MirrorMode, PackageConfig, S3Config, S3Credentials,
+        WebDavCredentials,
     },
+    destination_backends,
+    filter_report::format_filter_report,
+    generate_presigned_urls,
+    migrate::migrate,
     mirror,
+    stats::MirrorStats,
+    stats_db::{format_history, run_history},
+    test_filters, verify,
+    verify::format_verify_report,
 };
 
 /* -------------------------------------------- MAIN ------------------------------------------- */
@@ -13,31 +38,386 @@ use conda_mirror::{
 /// The main entrypoint for the conda-mirror CLI.
 #[tokio::main]
 async fn main() -> miette::Result<()> {
-    let cli_config = CliConfig::parse();
+    let mut cli_config = CliConfig::parse();
 
-    tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(cli_config.verbose)
-        .init();
+    let mut max_level = cli_config.verbose.tracing_level_filter();
+    if cli_config.verbose.is_silent() {
+        // --quiet hides progress bars and status messages, errors are still logged.
+        max_level = LevelFilter::ERROR;
+    }
+    if cli_config.interval_secs.is_some() {
+        // Log lines replace the progress bars in watch mode.
+        max_level = max_level.max(LevelFilter::INFO);
+    }
+    let fmt_layer = match cli_config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .boxed(),
+    };
+    let subscriber = tracing_subscriber::registry().with(fmt_layer.with_filter(max_level));
+    #[cfg(feature = "opentelemetry")]
+    let (subscriber, _tracer_provider) = {
+        let tracer_provider = otlp_tracer_provider()?;
+        let otel_layer = tracer_provider.as_ref().map(|provider| {
+            // The spans are exported independently of the verbosity of the log output.
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+                .with_filter(LevelFilter::INFO)
+        });
+        (
+            subscriber.with(otel_layer),
+            tracer_provider.map(TracerProviderGuard),
+        )
+    };
+    subscriber.init();
 
     tracing::debug!("Starting conda-mirror CLI");
     tracing::debug!("Parsed CLI options: {:?}", cli_config);
 
-    let yaml_config = if let Some(config_path) = cli_config.config {
+    match cli_config.command.take() {
+        Some(Command::Migrate(args)) => {
+            let yaml_config = read_yaml_config(cli_config.config.as_deref())?;
+            let destination = args
+                .destination
+                .or(yaml_config.destination)
+                .ok_or(miette::miette!("Destination must be specified"))?;
+            return migrate(destination, args.subdir, args.delete_originals).await;
+        }
+        Some(Command::ListBackends) => {
+            for (backend, schemes) in destination_backends() {
+                let schemes = schemes
+                    .iter()
+                    .map(|scheme| format!("{scheme}://"))
+                    .collect::<Vec<_>>();
+                println!("{:<12} {}", backend, schemes.join(", "));
+            }
+            return Ok(());
+        }
+        Some(Command::GenerateConfig(args)) => {
+            match args.output {
+                Some(path) => {
+                    if path.exists() {
+                        return Err(miette::miette!(
+                            "Not overwriting existing file {}",
+                            path.display()
+                        ));
+                    }
+                    std::fs::write(&path, EXAMPLE_CONFIG).map_err(|e| {
+                        miette::miette!("Could not write {}: {}", path.display(), e)
+                    })?;
+                    eprintln!("Wrote example configuration to {}", path.display());
+                }
+                None => print!("{EXAMPLE_CONFIG}"),
+            }
+            return Ok(());
+        }
+        Some(Command::GenerateCompletions(args)) => {
+            clap_complete::generate(
+                args.shell,
+                &mut CliConfig::command(),
+                env!("CARGO_BIN_NAME"),
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Some(Command::TestFilters(args)) => {
+            let config = build_config(cli_config)?;
+            let report =
+                test_filters(&config, args.subdir, args.source_from_file.as_deref()).await?;
+            print!("{}", format_filter_report(&report));
+            return Ok(());
+        }
+        Some(Command::GeneratePresignedUrls(args)) => {
+            let config = build_config(cli_config)?;
+            let urls = generate_presigned_urls(
+                &config,
+                args.subdir,
+                args.package.as_deref(),
+                Duration::from_secs(args.expires_in),
+            )
+            .await?;
+            for url in urls {
+                println!("{url}");
+            }
+            return Ok(());
+        }
+        Some(Command::Validate) => {
+            let diagnostics = validate(cli_config);
+            if diagnostics.is_empty() {
+                println!("Config is valid");
+                return Ok(());
+            }
+            for diagnostic in &diagnostics {
+                eprintln!("{diagnostic:?}");
+            }
+            return Err(miette::miette!(
+                "Found {} problem{} in the configuration",
+                diagnostics.len(),
+                if diagnostics.len() == 1 { "" } else { "s" }
+            ));
+        }
+        None => {}
+    }
+
+    if let Some(limit) = cli_config.show_stats_history {
+        let yaml_config = read_yaml_config(cli_config.config.as_deref())?;
+        let stats_db_path = cli_config
+            .mirror_stats_db
+            .or(yaml_config.mirror_stats_db)
+            .ok_or(miette::miette!(
+                "--show-stats-history requires --mirror-stats-db"
+            ))?;
+        let runs = run_history(&stats_db_path, limit).await?;
+        print!("{}", format_history(&runs));
+        return Ok(());
+    }
+
+    if cli_config.verify_only {
+        let config = build_config(cli_config)?;
+        let report = verify(&config).await?;
+        print!("{}", format_verify_report(&report));
+        if !report.is_ok() {
+            return Err(miette::miette!(
+                "Found {} missing and {} corrupted packages in {}",
+                report.missing.len(),
+                report.corrupted.len(),
+                config.destination
+            ));
+        }
+        return Ok(());
+    }
+
+    if cli_config.check_completeness {
+        let config = build_config(cli_config)?;
+        let report = check_completeness(&config).await?;
+        print!("{}", format_completeness_report(&report));
+        if !report.is_ok() {
+            return Err(miette::miette!(
+                "{} of {} expected packages are missing in {}",
+                report.missing.len(),
+                report.expected,
+                config.destination
+            ));
+        }
+        return Ok(());
+    }
+
+    if let Some(interval_secs) = cli_config.interval_secs {
+        return watch(cli_config, Duration::from_secs(interval_secs)).await;
+    }
+
+    let config = build_config(cli_config)?;
+    tracing::info!("Using configuration: {:?}", config);
+
+    let quiet = config.quiet;
+    let stats = mirror(config, shutdown_token(quiet, None)).await?;
+    tracing::info!("Mirror statistics: {:?}", stats);
+    if !stats.failures.is_empty() {
+        return Err(miette::miette!(
+            "Mirroring failed for {}",
+            failed_items(&stats)
+        ));
+    }
+    Ok(())
+}
+
+/// Mirrors the channel every `interval` until the process is asked to shut down.
+///
+/// The configuration file is read again before every run so that changes take effect without a
+/// restart. A shutdown signal lets the current run finish before exiting.
+async fn watch(cli_config: CliConfig, interval: Duration) -> miette::Result<()> {
+    if let Some(pid_file) = &cli_config.pid_file {
+        std::fs::write(pid_file, std::process::id().to_string()).map_err(|e| {
+            miette::miette!("Could not write PID file {}: {}", pid_file.display(), e)
+        })?;
+    }
+
+    let shutdown = shutdown_token(cli_config.verbose.is_silent(), cli_config.pid_file.clone());
+    loop {
+        let start = Instant::now();
+        tracing::info!("Starting mirror run");
+        let result = match build_config(cli_config.clone()) {
+            Ok(mut config) => {
+                config.hide_progress = true;
+                // Runs are not interrupted on shutdown, the loop stops after the current one.
+                mirror(config, CancellationToken::new()).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(stats) if !stats.failures.is_empty() => tracing::error!(
+                "Mirror run failed for {} after {:.1?}: {:?}",
+                failed_items(&stats),
+                start.elapsed(),
+                stats
+            ),
+            Ok(stats) => tracing::info!(
+                "Finished mirror run after {:.1?}: {:?}",
+                start.elapsed(),
+                stats
+            ),
+            Err(e) => tracing::error!("Mirror run failed after {:.1?}: {:?}", start.elapsed(), e),
+        }
+
+        tracing::info!("Next mirror run in {:?}", interval);
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+
+    if let Some(pid_file) = &cli_config.pid_file {
+        std::fs::remove_file(pid_file).into_diagnostic()?;
+    }
+    Ok(())
+}
+
+/// Checks the configuration without making any network requests, returns all problems found.
+fn validate(cli_config: CliConfig) -> Vec<miette::Report> {
+    let mut diagnostics = Vec::new();
+
+    match build_config(cli_config) {
+        Ok(config) => match current_dir() {
+            Ok(root_dir) => {
+                let channel_config = ChannelConfig::default_with_root_dir(root_dir);
+                for channel in config
+                    .sources()
+                    .chain(&config.fallback_sources)
+                    .chain(config.destinations())
+                {
+                    if let Err(e) = channel.clone().into_channel(&channel_config) {
+                        diagnostics.push(miette::miette!("Invalid channel {}: {}", channel, e));
+                    }
+                }
+                let s3_configs = [
+                    ("source", &config.source, config.s3_config_source.is_some()),
+                    (
+                        "destination",
+                        &config.destination,
+                        config.s3_config_destination.is_some(),
+                    ),
+                ];
+                for (side, channel, has_s3_config) in s3_configs {
+                    let is_s3 =
+                        matches!(channel, NamedChannelOrUrl::Url(url) if url.scheme() == "s3");
+                    if has_s3_config && !is_s3 {
+                        diagnostics.push(miette::miette!(
+                            "An S3 configuration is set for the {} but it is not an S3 URL: {}",
+                            side,
+                            channel
+                        ));
+                    }
+                }
+            }
+            Err(e) => diagnostics.push(miette::miette!(
+                "Could not determine the current directory: {}",
+                e
+            )),
+        },
+        Err(e) => diagnostics.push(e),
+    }
+    diagnostics
+}
+
+/// Selects the mirror mode from the package filters of the configuration file.
+fn mirror_mode(
+    include: Option<Vec<PackageConfig>>,
+    exclude: Option<Vec<PackageConfig>>,
+    intersection: Option<Vec<PackageConfig>>,
+) -> miette::Result<MirrorMode> {
+    match (include, exclude, intersection) {
+        (Some(include), Some(exclude), None) => Ok(MirrorMode::IncludeExclude(include, exclude)),
+        (Some(include), None, None) => Ok(MirrorMode::OnlyInclude(include)),
+        (None, Some(exclude), None) => Ok(MirrorMode::AllButExclude(exclude)),
+        (None, None, Some(intersection)) => Ok(MirrorMode::Intersection(intersection)),
+        (None, None, None) => Ok(MirrorMode::All),
+        (_, _, Some(_)) => Err(miette::miette!(
+            "`intersection` cannot be combined with `include` or `exclude`"
+        )),
+    }
+}
+
+/// Creates an OTLP exporter for the spans of the run if `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+///
+/// The exporter reads the other `OTEL_EXPORTER_OTLP_*` variables, e.g. for headers, as well.
+#[cfg(feature = "opentelemetry")]
+fn otlp_tracer_provider() -> miette::Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>> {
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .into_diagnostic()?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(env!("CARGO_PKG_NAME"))
+        .build();
+    Ok(Some(
+        opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build(),
+    ))
+}
+
+/// Flushes the remaining spans when `main` returns.
+#[cfg(feature = "opentelemetry")]
+struct TracerProviderGuard(opentelemetry_sdk::trace::SdkTracerProvider);
+
+#[cfg(feature = "opentelemetry")]
+impl Drop for TracerProviderGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            eprintln!("Could not export the remaining spans: {e}");
+        }
+    }
+}
+
+fn read_yaml_config(config_path: Option<&Path>) -> miette::Result<CondaMirrorYamlConfig> {
+    let yaml_config = if let Some(config_path) = config_path {
         let config_str = std::fs::read_to_string(config_path).into_diagnostic()?;
         serde_yml::from_str::<CondaMirrorYamlConfig>(&config_str).into_diagnostic()?
     } else {
         Default::default()
     };
-
     tracing::debug!("Parsed YAML configuration: {:?}", yaml_config);
+    Ok(yaml_config)
+}
+
+/// Merges the CLI options and the configuration file, CLI options take precedence.
+fn build_config(cli_config: CliConfig) -> miette::Result<CondaMirrorConfig> {
+    let yaml_config = read_yaml_config(cli_config.config.as_deref())?;
 
-    let (source, destination) = match (cli_config.source, cli_config.destination) {
-        (Some(source), Some(destination)) => (source, destination),
+    if yaml_config.source.is_some() && yaml_config.sources.is_some() {
+        return Err(miette::miette!(
+            "`source` and `sources` cannot be used together"
+        ));
+    }
+    if yaml_config.destination.is_some() && yaml_config.destinations.is_some() {
+        return Err(miette::miette!(
+            "`destination` and `destinations` cannot be used together"
+        ));
+    }
+    let (sources, destinations) = match (cli_config.source, cli_config.destination) {
+        (Some(source), Some(destination)) => (vec![source], vec![destination]),
         (None, None) => {
-            if let (Some(source), Some(destination)) =
-                (yaml_config.source.clone(), yaml_config.destination.clone())
-            {
-                (source, destination)
+            let sources = yaml_config
+                .sources
+                .clone()
+                .or(yaml_config.source.clone().map(|source| vec![source]))
+                .filter(|sources| !sources.is_empty());
+            let destinations = yaml_config
+                .destinations
+                .clone()
+                .or(yaml_config
+                    .destination
+                    .clone()
+                    .map(|destination| vec![destination]))
+                .filter(|destinations| !destinations.is_empty());
+            if let (Some(sources), Some(destinations)) = (sources, destinations) {
+                (sources, destinations)
             } else {
                 return Err(miette::miette!("Source and target must be specified"));
             }
@@ -51,12 +431,26 @@ async fn main() -> miette::Result<()> {
         yaml_config.subdirs.clone()
     };
 
-    let mode = match (yaml_config.include, yaml_config.exclude) {
-        (Some(include), Some(exclude)) => MirrorMode::IncludeExclude(include, exclude),
-        (Some(include), None) => MirrorMode::OnlyInclude(include),
-        (None, Some(exclude)) => MirrorMode::AllButExclude(exclude),
-        (None, None) => MirrorMode::All,
+    // Patterns from the CLI replace the ones from the configuration file, per list.
+    let cli_patterns = |matchspecs: Vec<MatchSpec>| {
+        (!matchspecs.is_empty()).then(|| matchspecs.into_iter().map(PackageConfig::from).collect())
     };
+    let mode = mirror_mode(
+        cli_patterns(cli_config.include).or(yaml_config.include),
+        cli_patterns(cli_config.exclude).or(yaml_config.exclude),
+        yaml_config.intersection,
+    )?;
+    let subdir_modes = yaml_config
+        .subdir_overrides
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(subdir, overrides)| {
+            Ok((
+                subdir,
+                mirror_mode(overrides.include, overrides.exclude, None)?,
+            ))
+        })
+        .collect::<miette::Result<HashMap<_, _>>>()?;
 
     let s3_config_destination = if let (Some(endpoint_url), Some(region), Some(force_path_style)) = (
         cli_config.s3_endpoint_url_destination,
@@ -67,6 +461,12 @@ async fn main() -> miette::Result<()> {
             endpoint_url,
             region,
             force_path_style,
+            sse: cli_config.s3_server_side_encryption,
+            kms_key_id: cli_config.s3_kms_key_id.clone(),
+            storage_class: cli_config.s3_storage_class.clone(),
+            checksum_algorithm: cli_config.s3_checksum_algorithm,
+            role_arn: cli_config.s3_role_arn.clone(),
+            external_id: cli_config.s3_external_id.clone(),
         })
     } else if let Some(s3_config_source_dest) = yaml_config.s3_config.clone() {
         if let Some(s3_config) = s3_config_source_dest.destination {
@@ -74,6 +474,17 @@ async fn main() -> miette::Result<()> {
                 endpoint_url: s3_config.endpoint_url,
                 region: s3_config.region,
                 force_path_style: s3_config.force_path_style,
+                sse: cli_config.s3_server_side_encryption.or(s3_config.sse),
+                kms_key_id: cli_config.s3_kms_key_id.clone().or(s3_config.kms_key_id),
+                storage_class: cli_config
+                    .s3_storage_class
+                    .clone()
+                    .or(s3_config.storage_class),
+                checksum_algorithm: cli_config
+                    .s3_checksum_algorithm
+                    .or(s3_config.checksum_algorithm),
+                role_arn: cli_config.s3_role_arn.clone().or(s3_config.role_arn),
+                external_id: cli_config.s3_external_id.clone().or(s3_config.external_id),
             })
         } else {
             None
@@ -90,6 +501,12 @@ async fn main() -> miette::Result<()> {
             endpoint_url,
             region,
             force_path_style,
+            sse: None,
+            kms_key_id: None,
+            storage_class: None,
+            checksum_algorithm: None,
+            role_arn: None,
+            external_id: None,
         })
     } else if let Some(s3_config_source_dest) = yaml_config.s3_config {
         if let Some(s3_config) = s3_config_source_dest.source {
@@ -97,6 +514,12 @@ async fn main() -> miette::Result<()> {
                 endpoint_url: s3_config.endpoint_url,
                 region: s3_config.region,
                 force_path_style: s3_config.force_path_style,
+                sse: s3_config.sse,
+                kms_key_id: s3_config.kms_key_id,
+                storage_class: s3_config.storage_class,
+                checksum_algorithm: s3_config.checksum_algorithm,
+                role_arn: s3_config.role_arn,
+                external_id: s3_config.external_id,
             })
         } else {
             None
@@ -118,6 +541,34 @@ async fn main() -> miette::Result<()> {
         None
     };
 
+    let webdav = cli_config.webdav || yaml_config.webdav.unwrap_or(false);
+    let webdav_credentials = if let (Some(username), Some(password)) = (
+        cli_config.webdav_username.or(yaml_config.webdav_username),
+        cli_config.webdav_password.or(yaml_config.webdav_password),
+    ) {
+        Some(WebDavCredentials {
+            username,
+            password: password.0,
+        })
+    } else {
+        None
+    };
+    let b2_credentials = if let (Some(application_key_id), Some(application_key)) = (
+        cli_config
+            .b2_application_key_id
+            .or(yaml_config.b2_application_key_id),
+        cli_config
+            .b2_application_key
+            .or(yaml_config.b2_application_key),
+    ) {
+        Some(B2Credentials {
+            application_key_id,
+            application_key: application_key.0,
+        })
+    } else {
+        None
+    };
+
     let s3_credentials_source = if let (Some(access_key_id), Some(secret_access_key)) = (
         cli_config.s3_access_key_id_source,
         cli_config.s3_secret_access_key_source,
@@ -131,18 +582,260 @@ async fn main() -> miette::Result<()> {
         None
     };
 
-    let config = CondaMirrorConfig {
-        source,
-        destination,
-        subdirs,
-        mode,
-        s3_config_source,
-        s3_config_destination,
-        s3_credentials_source,
-        s3_credentials_destination,
+    let write_jlap = cli_config.write_jlap || yaml_config.write_jlap.unwrap_or(false);
+    let write_manifest = cli_config.write_manifest || yaml_config.write_manifest.unwrap_or(false);
+    let use_http2 = match (cli_config.enable_http2, cli_config.disable_http2) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => yaml_config.use_http2,
     };
+    let write_sha256_sidecar =
+        cli_config.write_sha256_sidecar || yaml_config.write_checksums_separately.unwrap_or(false);
+    let connect_timeout_secs = cli_config
+        .connect_timeout_secs
+        .or(yaml_config.connect_timeout_secs);
+    let read_timeout_secs = cli_config
+        .read_timeout_secs
+        .or(yaml_config.read_timeout_secs);
+    let request_timeout_secs = cli_config
+        .request_timeout_secs
+        .or(yaml_config.request_timeout_secs);
+    let bytes_per_ms_timeout = cli_config
+        .bytes_per_ms_timeout
+        .or(yaml_config.bytes_per_ms_timeout);
+    let source_probe_timeout_ms = cli_config
+        .source_probe_timeout_ms
+        .or(yaml_config.source_probe_timeout_ms);
+    let retry_delay_override_secs = cli_config
+        .retry_delay_override_secs
+        .or(yaml_config.retry_delay_override_secs);
+    let max_delete_fraction = cli_config
+        .max_delete_fraction
+        .or(yaml_config.max_delete_fraction);
+    let preload_repodata =
+        cli_config.preload_repodata || yaml_config.preload_repodata.unwrap_or(false);
+    let sign_packages = cli_config.sign_packages || yaml_config.sign_packages.unwrap_or(false);
+    let signing_key_path = cli_config.signing_key_path.or(yaml_config.signing_key_path);
+    let signing_key_id = cli_config.signing_key_id.or(yaml_config.signing_key_id);
+    let bearer_token = cli_config
+        .bearer_token
+        .or(yaml_config.bearer_token)
+        .map(|token| token.0);
+    let quetz_token = cli_config
+        .quetz_token
+        .or(yaml_config.quetz_token)
+        .map(|token| token.0);
+    let conda_token = cli_config
+        .conda_token
+        .or(yaml_config.conda_token)
+        .map(|token| token.0);
+    let quetz_token_location = cli_config
+        .quetz_token_location
+        .or(yaml_config.quetz_token_location)
+        .unwrap_or_default();
+    let source_display_name = cli_config
+        .source_display_name
+        .or(yaml_config.source_display_name);
 
-    tracing::info!("Using configuration: {:?}", config);
+    CondaMirrorConfig::builder()
+        .sources(sources)
+        .fallback_sources(if cli_config.fallback_sources.is_empty() {
+            yaml_config.fallback_sources.unwrap_or_default()
+        } else {
+            cli_config.fallback_sources
+        })
+        .conflict_resolution(yaml_config.conflict_resolution.unwrap_or_default())
+        .source_type(
+            cli_config
+                .source_type
+                .or(yaml_config.source_type)
+                .unwrap_or_default(),
+        )
+        .destinations(destinations)
+        .subdirs(subdirs)
+        .include_subdirs(if cli_config.include_subdir.is_empty() {
+            yaml_config.include_subdirs.unwrap_or_default()
+        } else {
+            cli_config.include_subdir
+        })
+        .exclude_subdirs(if cli_config.exclude_subdir.is_empty() {
+            yaml_config.exclude_subdirs.unwrap_or_default()
+        } else {
+            cli_config.exclude_subdir
+        })
+        .subdir_aliases(yaml_config.subdir_aliases.unwrap_or_default())
+        .mode(mode)
+        .subdir_modes(subdir_modes)
+        .s3_config_source(s3_config_source)
+        .s3_config_destination(s3_config_destination)
+        .s3_credentials_source(s3_credentials_source)
+        .s3_credentials_destination(s3_credentials_destination)
+        .webdav(webdav)
+        .webdav_credentials(webdav_credentials)
+        .b2_credentials(b2_credentials)
+        .b2_bucket_id(cli_config.b2_bucket_id.or(yaml_config.b2_bucket_id))
+        .sftp_private_key_path(
+            cli_config
+                .sftp_private_key_path
+                .or(yaml_config.sftp_private_key_path),
+        )
+        .sftp_host_key_check(
+            cli_config
+                .sftp_host_key_check
+                .or(yaml_config.sftp_host_key_check)
+                .unwrap_or(true),
+        )
+        .write_jlap(write_jlap)
+        .write_sha256_sidecar(write_sha256_sidecar)
+        .write_manifest(write_manifest)
+        .package_listing_path(
+            cli_config
+                .package_listing_path
+                .or(yaml_config.write_package_listing),
+        )
+        .connect_timeout_secs(connect_timeout_secs)
+        .read_timeout_secs(read_timeout_secs)
+        .request_timeout_secs(request_timeout_secs)
+        .bytes_per_ms_timeout(bytes_per_ms_timeout)
+        .source_max_redirects(
+            cli_config
+                .source_max_redirects
+                .or(yaml_config.source_max_redirects),
+        )
+        .use_http2(use_http2)
+        .user_agent(cli_config.user_agent.or(yaml_config.user_agent))
+        .bandwidth_limit_bytes_per_sec(
+            cli_config
+                .bandwidth_limit_bytes_per_sec
+                .or(yaml_config.bandwidth_limit_bytes_per_sec),
+        )
+        .source_probe_timeout_ms(source_probe_timeout_ms)
+        .retry_delay_override_secs(retry_delay_override_secs)
+        .retry_max_retries(
+            cli_config
+                .retry_max_retries
+                .or(yaml_config.retry_max_retries),
+        )
+        .retry_initial_interval_ms(
+            cli_config
+                .retry_initial_interval_ms
+                .or(yaml_config.retry_initial_interval_ms),
+        )
+        .retry_multiplier(cli_config.retry_multiplier.or(yaml_config.retry_multiplier))
+        .opendal_circuit_breaker(
+            cli_config.enable_opendal_circuit_breaker
+                || yaml_config.enable_opendal_circuit_breaker.unwrap_or(false),
+        )
+        .circuit_breaker_threshold(
+            cli_config
+                .circuit_breaker_threshold
+                .or(yaml_config.circuit_breaker_threshold),
+        )
+        .circuit_breaker_cooldown_secs(
+            cli_config
+                .circuit_breaker_cooldown_secs
+                .or(yaml_config.circuit_breaker_cooldown_secs),
+        )
+        .max_delete_fraction(max_delete_fraction)
+        .force_delete(cli_config.force_delete)
+        .soft_delete(cli_config.soft_delete || yaml_config.soft_delete.unwrap_or(false))
+        .trash_prefix(cli_config.trash_prefix.or(yaml_config.trash_prefix))
+        .smart_delete_runs(cli_config.smart_delete_runs.or(yaml_config.smart_delete))
+        .preserve_removed(
+            cli_config.preserve_removed || yaml_config.preserve_removed.unwrap_or(false),
+        )
+        .fail_on_missing_checksums(
+            cli_config.fail_on_missing_checksums
+                || yaml_config.fail_on_missing_checksums.unwrap_or(false),
+        )
+        .force_redownload(cli_config.force_redownload)
+        .delete_only(cli_config.delete_only)
+        .output_tsv(cli_config.output_tsv)
+        .output_changed_packages_only(cli_config.output_changed_packages_only)
+        .changed_packages_path(cli_config.output_file)
+        .preload_repodata(preload_repodata)
+        .metrics_port(cli_config.metrics_port.or(yaml_config.metrics_port))
+        .webhook_url(cli_config.webhook_url.or(yaml_config.webhook_url))
+        .webhook_on_failure_only(
+            cli_config.webhook_on_failure_only
+                || yaml_config.webhook_on_failure_only.unwrap_or(false),
+        )
+        .stats_db_path(cli_config.mirror_stats_db.or(yaml_config.mirror_stats_db))
+        .since(cli_config.since.or(yaml_config.since))
+        .channel_prune_age_days(
+            cli_config
+                .channel_prune_age_days
+                .or(yaml_config.channel_prune_age_days),
+        )
+        .with_deps(cli_config.with_deps || yaml_config.with_deps.unwrap_or(false))
+        .exclude_prerelease(
+            cli_config.exclude_prerelease || yaml_config.exclude_prerelease.unwrap_or(false),
+        )
+        .max_packages(cli_config.max_packages.or(yaml_config.max_packages))
+        .package_name_transform_command(
+            cli_config
+                .package_name_transform_command
+                .or(yaml_config.package_name_transform),
+        )
+        .quiet(cli_config.verbose.is_silent())
+        .resume(cli_config.resume)
+        .checkpoint_path(cli_config.checkpoint_file)
+        .sign_packages(sign_packages)
+        .signing_key_path(signing_key_path)
+        .signing_key_id(signing_key_id)
+        .bearer_token(bearer_token)
+        .quetz_token(quetz_token)
+        .quetz_token_location(quetz_token_location)
+        .conda_token(conda_token)
+        .source_display_name(source_display_name)
+        .build()
+}
+
+/// Joins the items of a mirror run that failed, e.g. `linux-64, noarch`.
+fn failed_items(stats: &MirrorStats) -> String {
+    stats
+        .failures
+        .iter()
+        .map(|(item, _)| item.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns a token that is cancelled once the process is asked to shut down.
+///
+/// A second signal terminates the process immediately, after removing `pid_file` if given.
+fn shutdown_token(quiet: bool, pid_file: Option<PathBuf>) -> CancellationToken {
+    let token = CancellationToken::new();
+    let shutdown = token.clone();
+    tokio::spawn(async move {
+        wait_for_shutdown_signal().await;
+        if !quiet {
+            eprintln!("🛑 Shutting down, waiting for running transfers to finish...");
+        }
+        shutdown.cancel();
+        wait_for_shutdown_signal().await;
+        if let Some(pid_file) = pid_file {
+            let _ = std::fs::remove_file(pid_file);
+        }
+        std::process::exit(130);
+    });
+    token
+}
+
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
 
-    mirror(config).await
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to listen for Ctrl-C");
 }
@@ -0,0 +1,39 @@
+use miette::IntoDiagnostic;
+use opendal::Operator;
+use rattler_conda_types::{Platform, RepoData};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    filename: &'a str,
+    sha256: Option<String>,
+    md5: Option<String>,
+    size: Option<u64>,
+}
+
+/// Writes `{subdir}/manifest.json` with the checksums and size of every package in `repodata`,
+/// sorted by filename.
+pub(crate) async fn write_manifest(
+    op: &Operator,
+    subdir: Platform,
+    repodata: &RepoData,
+) -> miette::Result<()> {
+    let mut entries = repodata
+        .packages
+        .iter()
+        .chain(repodata.conda_packages.iter())
+        .map(|(filename, record)| ManifestEntry {
+            filename,
+            sha256: record.sha256.map(|sha256| format!("{sha256:x}")),
+            md5: record.md5.map(|md5| format!("{md5:x}")),
+            size: record.size,
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.filename);
+
+    let manifest_path = format!("{}/manifest.json", subdir.as_str());
+    let manifest = serde_json::to_vec_pretty(&entries).into_diagnostic()?;
+    op.write(&manifest_path, manifest).await.into_diagnostic()?;
+    tracing::info!("Wrote {} packages to {}", entries.len(), manifest_path);
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use http_body_util::Full;
+use hyper::{
+    Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, atomic::Ordering},
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::stats::MirrorCounters;
+
+/// Starts an HTTP server that exposes the counters of a mirror run in the Prometheus text format
+/// on `/metrics`.
+///
+/// The server is stopped when the returned [`MetricsServer`] is dropped.
+pub(crate) async fn start_metrics_server(
+    port: u16,
+    counters: Arc<MirrorCounters>,
+) -> miette::Result<MetricsServer> {
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port)))
+        .await
+        .map_err(|e| miette::miette!("Could not bind metrics server to port {}: {}", port, e))?;
+    tracing::info!("Serving metrics on http://0.0.0.0:{}/metrics", port);
+
+    Ok(MetricsServer(tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept metrics connection: {}", e);
+                    continue;
+                }
+            };
+            let counters = counters.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let counters = counters.clone();
+                    async move { Ok::<_, Infallible>(handle_request(request, &counters)) }
+                });
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("Failed to serve metrics connection: {}", e);
+                }
+            });
+        }
+    })))
+}
+
+/// Handle to a running metrics server, aborts the server when dropped.
+pub(crate) struct MetricsServer(JoinHandle<()>);
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn handle_request(request: Request<Incoming>, counters: &MirrorCounters) -> Response<Full<Bytes>> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Full::new(Bytes::from_static(b"Not Found\n")));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let mut response = Response::new(Full::new(Bytes::from(render_metrics(counters))));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
+
+fn render_metrics(counters: &MirrorCounters) -> String {
+    let metrics = [
+        (
+            "conda_mirror_packages_added_total",
+            "counter",
+            "Number of packages that were copied to the destination.",
+            counters.packages_added.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "conda_mirror_packages_deleted_total",
+            "counter",
+            "Number of packages that were deleted from the destination.",
+            counters.packages_deleted.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "conda_mirror_bytes_downloaded_total",
+            "counter",
+            "Number of bytes that were downloaded from the source.",
+            counters.bytes_downloaded.load(Ordering::Relaxed),
+        ),
+        (
+            "conda_mirror_active_tasks",
+            "gauge",
+            "Number of packages that are currently being transferred or deleted.",
+            counters.active_tasks.load(Ordering::Relaxed) as u64,
+        ),
+    ];
+
+    let mut output = String::new();
+    for (name, metric_type, help, value) in metrics {
+        let _ = writeln!(output, "# HELP {name} {help}");
+        let _ = writeln!(output, "# TYPE {name} {metric_type}");
+        let _ = writeln!(output, "{name} {value}");
+    }
+    output
+}
@@ -0,0 +1,157 @@
+use http::{Extensions, HeaderName, HeaderValue, header::AUTHORIZATION};
+use reqwest_middleware::{
+    Middleware, Next,
+    reqwest::{Request, Response},
+};
+
+use crate::config::QuetzTokenLocation;
+
+/// Header that Quetz reads API keys from.
+const QUETZ_API_KEY_HEADER: HeaderName = HeaderName::from_static("x-quetz-api-key");
+
+/// Adds an `Authorization: Bearer <token>` header to all requests to the given host.
+pub(crate) struct BearerTokenMiddleware {
+    host: String,
+    header_value: HeaderValue,
+}
+
+impl BearerTokenMiddleware {
+    pub(crate) fn new(host: String, token: &str) -> miette::Result<Self> {
+        let mut header_value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|_| miette::miette!("Bearer token contains invalid characters"))?;
+        header_value.set_sensitive(true);
+        Ok(Self { host, header_value })
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for BearerTokenMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if req.url().host_str() == Some(self.host.as_str()) {
+            req.headers_mut()
+                .insert(AUTHORIZATION, self.header_value.clone());
+        }
+        next.run(req, extensions).await
+    }
+}
+
+/// Domains whose requests get the conda token, including their subdomains.
+const CONDA_TOKEN_DOMAINS: &[&str] = &["anaconda.org", "conda.io"];
+
+/// Appends an anaconda.org token as `?token=<token>` to all requests to anaconda.org and conda.io.
+pub(crate) struct CondaTokenMiddleware {
+    token: String,
+}
+
+impl CondaTokenMiddleware {
+    pub(crate) fn new(token: &str) -> Self {
+        Self {
+            token: token.to_string(),
+        }
+    }
+}
+
+fn is_conda_token_host(host: &str) -> bool {
+    CONDA_TOKEN_DOMAINS.iter().any(|domain| {
+        host == *domain
+            || host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.ends_with('.'))
+    })
+}
+
+#[async_trait::async_trait]
+impl Middleware for CondaTokenMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        // Redirects and retries may pass the same URL again.
+        if req.url().host_str().is_some_and(is_conda_token_host)
+            && !req.url().query_pairs().any(|(key, _)| key == "token")
+        {
+            req.url_mut()
+                .query_pairs_mut()
+                .append_pair("token", &self.token);
+        }
+        next.run(req, extensions).await
+    }
+}
+
+/// Passes a Quetz token to all requests to the given host, either in the `X-Quetz-API-Key` header
+/// or embedded in the URL as `/t/<token>/...`.
+pub(crate) struct QuetzTokenMiddleware {
+    host: String,
+    token: String,
+    header_value: HeaderValue,
+    location: QuetzTokenLocation,
+}
+
+impl QuetzTokenMiddleware {
+    pub(crate) fn new(
+        host: String,
+        token: &str,
+        location: QuetzTokenLocation,
+    ) -> miette::Result<Self> {
+        let mut header_value = HeaderValue::from_str(token)
+            .map_err(|_| miette::miette!("Quetz token contains invalid characters"))?;
+        header_value.set_sensitive(true);
+        Ok(Self {
+            host,
+            token: token.to_string(),
+            header_value,
+            location,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for QuetzTokenMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if req.url().host_str() == Some(self.host.as_str()) {
+            match self.location {
+                QuetzTokenLocation::Header => {
+                    req.headers_mut()
+                        .insert(QUETZ_API_KEY_HEADER, self.header_value.clone());
+                }
+                QuetzTokenLocation::Url => {
+                    // Redirects and retries may pass the same URL again.
+                    if !req.url().path().starts_with("/t/") {
+                        let path = format!("/t/{}{}", self.token, req.url().path());
+                        req.url_mut().set_path(&path);
+                    }
+                }
+            }
+        }
+        next.run(req, extensions).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conda_token_hosts() {
+        assert!(is_conda_token_host("anaconda.org"));
+        assert!(is_conda_token_host("conda.anaconda.org"));
+        assert!(is_conda_token_host("api.anaconda.org"));
+        assert!(is_conda_token_host("repo.conda.io"));
+        assert!(!is_conda_token_host("evilanaconda.org"));
+        assert!(!is_conda_token_host("notconda.io"));
+        assert!(!is_conda_token_host("anaconda.org.evil.com"));
+        assert!(!is_conda_token_host("example.com"));
+    }
+}
@@ -0,0 +1,295 @@
+use futures::{StreamExt, stream::FuturesUnordered};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use miette::IntoDiagnostic;
+use rattler_conda_types::{
+    ChannelConfig, ChannelInfo, NamedChannelOrUrl, PackageRecord, Platform, RepoData,
+    package::ArchiveType,
+};
+use rattler_digest::{Md5, Sha256, compute_file_digest};
+use rattler_index::{package_record_from_tar_bz2, write_repodata};
+use rattler_package_streaming::write::{CompressionLevel, write_conda_package};
+use std::{
+    env::current_dir,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use tokio::sync::Semaphore;
+
+use crate::create_operator;
+
+/// Converts all `.tar.bz2` packages of a local channel to `.conda` packages.
+///
+/// The converted packages are added to the `packages.conda` section of the repodata. If
+/// `delete_originals` is set, the `.tar.bz2` packages are deleted and removed from the repodata
+/// afterwards.
+pub async fn migrate(
+    channel: NamedChannelOrUrl,
+    subdirs: Option<Vec<Platform>>,
+    delete_originals: bool,
+) -> miette::Result<()> {
+    let channel_url = channel
+        .clone()
+        .into_channel(&ChannelConfig::default_with_root_dir(
+            current_dir().into_diagnostic()?,
+        ))
+        .into_diagnostic()?
+        .base_url
+        .url()
+        .clone();
+    if channel_url.scheme() != "file" {
+        return Err(miette::miette!(
+            "Only local channels can be migrated, got {}",
+            channel_url
+        ));
+    }
+    let channel_path = channel_url
+        .to_file_path()
+        .map_err(|_| miette::miette!("Could not convert URL to file path"))?;
+
+    let subdirs = match subdirs {
+        Some(subdirs) => subdirs,
+        None => local_subdirs(&channel_path)?,
+    };
+    tracing::info!("Migrating the following subdirs: {:?}", subdirs);
+
+    eprintln!("🚚 Migrating {} to .conda packages...", channel);
+
+    let multi_progress = Arc::new(MultiProgress::new());
+    let max_parallel = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let semaphore = Arc::new(Semaphore::new(max_parallel));
+
+    let mut tasks = FuturesUnordered::new();
+    for subdir in subdirs {
+        let channel_path = channel_path.clone();
+        let multi_progress = multi_progress.clone();
+        let semaphore = semaphore.clone();
+        tasks.push(tokio::spawn(migrate_subdir(
+            channel_path,
+            subdir,
+            delete_originals,
+            multi_progress,
+            semaphore,
+        )));
+    }
+
+    while let Some(join_result) = tasks.next().await {
+        match join_result {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                tracing::error!("Failed to migrate subdir: {}", e);
+                tasks.clear();
+                return Err(e);
+            }
+            Err(join_err) => {
+                tracing::error!("Task panicked: {}", join_err);
+                tasks.clear();
+                return Err(miette::miette!("Task panicked: {}", join_err));
+            }
+        }
+    }
+
+    eprintln!("✅ Migration completed");
+    Ok(())
+}
+
+/// Returns all subdirectories of a local channel that are named like a platform.
+fn local_subdirs(channel_path: &Path) -> miette::Result<Vec<Platform>> {
+    let mut subdirs = Vec::new();
+    for entry in std::fs::read_dir(channel_path).into_diagnostic()? {
+        let entry = entry.into_diagnostic()?;
+        if !entry.file_type().into_diagnostic()?.is_dir() {
+            continue;
+        }
+        if let Ok(subdir) = Platform::from_str(&entry.file_name().to_string_lossy()) {
+            subdirs.push(subdir);
+        }
+    }
+    Ok(subdirs)
+}
+
+async fn migrate_subdir(
+    channel_path: PathBuf,
+    subdir: Platform,
+    delete_originals: bool,
+    progress: Arc<MultiProgress>,
+    semaphore: Arc<Semaphore>,
+) -> miette::Result<()> {
+    let subdir_path = channel_path.join(subdir.as_str());
+    let repodata_path = subdir_path.join("repodata.json");
+    let mut repodata = if repodata_path.exists() {
+        RepoData::from_path(&repodata_path).into_diagnostic()?
+    } else {
+        RepoData {
+            info: Some(ChannelInfo {
+                subdir: Some(subdir.to_string()),
+                base_url: None,
+            }),
+            packages: Default::default(),
+            conda_packages: Default::default(),
+            removed: Default::default(),
+            version: Some(2),
+        }
+    };
+
+    let mut tar_bz2_packages = Vec::new();
+    for entry in std::fs::read_dir(&subdir_path).into_diagnostic()? {
+        let filename = entry.into_diagnostic()?.file_name();
+        let filename = filename.to_string_lossy();
+        if let Some((stem, ArchiveType::TarBz2)) = ArchiveType::split_str(&filename) {
+            tar_bz2_packages.push(stem.to_string());
+        }
+    }
+    tracing::info!(
+        "Migrating {} .tar.bz2 packages in {}",
+        tar_bz2_packages.len(),
+        subdir
+    );
+
+    let pb = progress.add(ProgressBar::new(tar_bz2_packages.len() as u64));
+    let sty = ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.yellow/blue} {pos:>7}/{len:7} {msg}",
+    )
+    .unwrap()
+    .progress_chars("##-");
+    pb.set_style(sty);
+
+    let mut tasks = FuturesUnordered::new();
+    for stem in tar_bz2_packages {
+        let tar_bz2_filename = format!("{stem}.tar.bz2");
+        let conda_filename = format!("{stem}.conda");
+        let original_record = repodata.packages.get(&tar_bz2_filename).cloned();
+        let subdir_path = subdir_path.clone();
+        let semaphore = semaphore.clone();
+        let pb = pb.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore
+                .acquire()
+                .await
+                .expect("Semaphore was unexpectedly closed");
+            pb.set_message(format!(
+                "Migrating {} {}",
+                subdir.as_str(),
+                console::style(&tar_bz2_filename).dim()
+            ));
+            let record = tokio::task::spawn_blocking(move || {
+                convert_package(&subdir_path, &stem, original_record)
+            })
+            .await
+            .into_diagnostic()??;
+            pb.inc(1);
+            Ok::<_, miette::Report>((tar_bz2_filename, conda_filename, record))
+        }));
+    }
+
+    while let Some(join_result) = tasks.next().await {
+        let (tar_bz2_filename, conda_filename, record) = match join_result {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => {
+                tasks.clear();
+                pb.abandon_with_message(format!(
+                    "{} {}",
+                    console::style("Failed to migrate packages in").red(),
+                    console::style(subdir.as_str()).dim()
+                ));
+                return Err(e);
+            }
+            Err(join_err) => {
+                tasks.clear();
+                pb.abandon_with_message(format!(
+                    "{} {}",
+                    console::style("Failed to migrate packages in").red(),
+                    console::style(subdir.as_str()).dim()
+                ));
+                return Err(miette::miette!("Task panicked: {}", join_err));
+            }
+        };
+        repodata.conda_packages.insert(conda_filename, record);
+        if delete_originals {
+            std::fs::remove_file(subdir_path.join(&tar_bz2_filename)).into_diagnostic()?;
+            repodata.packages.remove(&tar_bz2_filename);
+        }
+    }
+    pb.finish_with_message(format!(
+        "{} {}",
+        console::style("Finished migrating packages in").green(),
+        subdir.as_str()
+    ));
+
+    let mut fs_config = opendal::services::FsConfig::default();
+    fs_config.root = Some(channel_path.to_string_lossy().to_string());
+    let op = create_operator(fs_config)?;
+    write_repodata(repodata, None, true, true, subdir, op)
+        .await
+        .map_err(|e| miette::miette!("Could not write repodata: {}", e))?;
+    Ok(())
+}
+
+/// Converts `<stem>.tar.bz2` in `subdir_path` to `<stem>.conda` and returns the repodata record of
+/// the new package.
+///
+/// If the `.conda` package already exists, it is not converted again.
+fn convert_package(
+    subdir_path: &Path,
+    stem: &str,
+    original_record: Option<PackageRecord>,
+) -> miette::Result<PackageRecord> {
+    let tar_bz2_path = subdir_path.join(format!("{stem}.tar.bz2"));
+    let conda_path = subdir_path.join(format!("{stem}.conda"));
+
+    let record = match original_record {
+        Some(record) => record,
+        None => package_record_from_tar_bz2(&tar_bz2_path).into_diagnostic()?,
+    };
+
+    if conda_path.exists() {
+        tracing::debug!("{} already exists, skipping", conda_path.display());
+    } else {
+        let extract_dir = tempfile::tempdir().into_diagnostic()?;
+        rattler_package_streaming::fs::extract_tar_bz2(&tar_bz2_path, extract_dir.path())
+            .into_diagnostic()?;
+        let mut paths = Vec::new();
+        collect_files(extract_dir.path(), &mut paths)?;
+
+        // Write to a temporary file first so that an interrupted conversion doesn't leave a
+        // broken package behind.
+        let mut conda_file = tempfile::NamedTempFile::new_in(subdir_path).into_diagnostic()?;
+        write_conda_package(
+            &mut conda_file,
+            extract_dir.path(),
+            &paths,
+            CompressionLevel::Default,
+            None,
+            stem,
+            record.timestamp.as_ref(),
+            None,
+        )
+        .into_diagnostic()?;
+        conda_file.persist(&conda_path).into_diagnostic()?;
+        tracing::debug!("Converted {} to {}", stem, conda_path.display());
+    }
+
+    // Keep all metadata apart from the checksums and size, e.g. in case the original record was
+    // patched.
+    Ok(PackageRecord {
+        sha256: Some(compute_file_digest::<Sha256>(&conda_path).into_diagnostic()?),
+        md5: Some(compute_file_digest::<Md5>(&conda_path).into_diagnostic()?),
+        size: Some(std::fs::metadata(&conda_path).into_diagnostic()?.len()),
+        ..record
+    })
+}
+
+fn collect_files(dir: &Path, paths: &mut Vec<PathBuf>) -> miette::Result<()> {
+    for entry in std::fs::read_dir(dir).into_diagnostic()? {
+        let entry = entry.into_diagnostic()?;
+        let path = entry.path();
+        if entry.file_type().into_diagnostic()?.is_dir() {
+            collect_files(&path, paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
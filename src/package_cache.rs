@@ -0,0 +1,95 @@
+use miette::IntoDiagnostic;
+use rattler_conda_types::{
+    ChannelInfo, PackageRecord, Platform, RepoData,
+    package::{ArchiveType, IndexJson},
+};
+use rattler_digest::{Md5, Sha256, compute_file_digest};
+use rattler_package_streaming::seek::{read_package_file, stream_conda_info};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Lists the subdirs of the packages in the package cache at `path`.
+pub(crate) fn package_cache_subdirs(path: &Path) -> miette::Result<Vec<Platform>> {
+    let subdirs = cached_packages(path)?
+        .into_iter()
+        .filter_map(|(_, _, record)| record.subdir.parse::<Platform>().ok())
+        .collect::<BTreeSet<_>>();
+    Ok(subdirs.into_iter().collect())
+}
+
+/// Generates the repodata of `subdir` from the packages in the package cache at `path`.
+///
+/// Only the `info/index.json` of every package is read to find its subdir, the checksums are
+/// computed for the packages of `subdir` only.
+pub(crate) fn package_cache_repodata(path: &Path, subdir: Platform) -> miette::Result<RepoData> {
+    let mut repodata = RepoData {
+        info: Some(ChannelInfo {
+            subdir: Some(subdir.to_string()),
+            base_url: None,
+        }),
+        packages: Default::default(),
+        conda_packages: Default::default(),
+        removed: Default::default(),
+        version: Some(2),
+    };
+    for (package_path, filename, mut record) in cached_packages(path)? {
+        if record.subdir != subdir.as_str() {
+            continue;
+        }
+        record.size = Some(package_path.metadata().into_diagnostic()?.len());
+        record.sha256 = Some(compute_file_digest::<Sha256>(&package_path).into_diagnostic()?);
+        record.md5 = Some(compute_file_digest::<Md5>(&package_path).into_diagnostic()?);
+        match ArchiveType::try_from(&filename) {
+            Some(ArchiveType::TarBz2) => repodata.packages.insert(filename, record),
+            Some(ArchiveType::Conda) => repodata.conda_packages.insert(filename, record),
+            None => unreachable!("Only packages are read from the package cache"),
+        };
+    }
+    Ok(repodata)
+}
+
+/// Reads the `info/index.json` of all packages in the package cache, without checksums.
+///
+/// Packages that can't be read, e.g. partial downloads, are skipped with a warning.
+fn cached_packages(path: &Path) -> miette::Result<Vec<(PathBuf, String, PackageRecord)>> {
+    let mut packages = Vec::new();
+    for entry in std::fs::read_dir(path)
+        .map_err(|e| miette::miette!("Could not read package cache {}: {}", path.display(), e))?
+    {
+        let package_path = entry.into_diagnostic()?.path();
+        let Some(filename) = package_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| ArchiveType::try_from(*name).is_some())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        if !package_path.is_file() {
+            continue;
+        }
+        let record = read_index_json(&package_path).and_then(|index| {
+            PackageRecord::from_index_json(index, None, None, None).into_diagnostic()
+        });
+        match record {
+            Ok(record) => packages.push((package_path, filename, record)),
+            Err(e) => tracing::warn!(
+                "Skipping {} in the package cache, it could not be read: {}",
+                package_path.display(),
+                e
+            ),
+        }
+    }
+    Ok(packages)
+}
+
+fn read_index_json(package_path: &Path) -> miette::Result<IndexJson> {
+    // `read_package_file` panics on `.conda` files that are not valid zip archives.
+    if ArchiveType::try_from(package_path) == Some(ArchiveType::Conda) {
+        stream_conda_info(File::open(package_path).into_diagnostic()?).into_diagnostic()?;
+    }
+    read_package_file::<IndexJson>(package_path).into_diagnostic()
+}
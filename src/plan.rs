@@ -0,0 +1,24 @@
+use rattler_conda_types::{PackageRecord, Platform, RepoData};
+use std::collections::HashMap;
+use url::Url;
+
+/// The changes mirroring a subdir makes to the destination, see `plan_mirror_subdir`.
+///
+/// Packages can be removed from `packages_to_add` and `packages_to_delete` before the plan is
+/// executed. Packages that are added have to be in `packages_to_mirror`.
+#[derive(Debug, Clone)]
+pub struct MirrorPlan {
+    pub subdir: Platform,
+    /// Packages that are copied to the destination.
+    pub packages_to_add: HashMap<String, PackageRecord>,
+    /// Filenames of the packages that are deleted from the destination. With `--smart-delete`,
+    /// packages that were not missing for long enough are kept when the plan is executed.
+    pub packages_to_delete: Vec<String>,
+    /// All packages in the repodata of the destination after mirroring, with their source URL.
+    pub packages_to_mirror: HashMap<String, (PackageRecord, Url)>,
+    /// Packages that were removed from the source but are kept in the destination and listed in
+    /// `removed` of its repodata, see `preserve_removed`.
+    pub packages_to_preserve: HashMap<String, PackageRecord>,
+    /// The repodata of the source, whose `info` and `removed` are carried over.
+    pub(crate) repodata: RepoData,
+}
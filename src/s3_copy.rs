@@ -0,0 +1,96 @@
+use opendal::Operator;
+use rattler_conda_types::NamedChannelOrUrl;
+use url::Url;
+
+use crate::{
+    config::CondaMirrorConfig, create_operator_with_layers, opendal_circuit_breaker_layer,
+    opendal_retry_layer,
+};
+
+/// Copies packages from an S3 source to an S3 destination in the same bucket using server-side
+/// copies, so that they don't have to be downloaded and uploaded again.
+pub(crate) struct S3Copy {
+    /// Operator on the root of the bucket, both source and destination keys are relative to it.
+    operator: Operator,
+    bucket: String,
+    /// Prefix of the destination channel in the bucket.
+    destination_root: String,
+}
+
+impl S3Copy {
+    /// Returns an [`S3Copy`] if the source is in the same bucket and on the same endpoint as the
+    /// destination described by `destination_config`.
+    pub(crate) fn new(
+        config: &CondaMirrorConfig,
+        destination_config: &opendal::services::S3Config,
+    ) -> miette::Result<Option<Self>> {
+        let (Some(s3_config_source), Some(s3_config_destination)) =
+            (&config.s3_config_source, &config.s3_config_destination)
+        else {
+            return Ok(None);
+        };
+        if s3_config_source.endpoint_url != s3_config_destination.endpoint_url {
+            return Ok(None);
+        }
+        let same_bucket = matches!(
+            &config.source,
+            NamedChannelOrUrl::Url(url)
+                if url.scheme() == "s3" && url.host_str() == Some(destination_config.bucket.as_str())
+        );
+        if !same_bucket {
+            return Ok(None);
+        }
+
+        let mut bucket_config = destination_config.clone();
+        let destination_root = bucket_config
+            .root
+            .take()
+            .unwrap_or_default()
+            .trim_matches('/')
+            .to_string();
+        bucket_config.root = Some("/".to_string());
+        tracing::info!(
+            "Source and destination are in bucket {}, using server-side copies",
+            destination_config.bucket
+        );
+        Ok(Some(Self {
+            operator: create_operator_with_layers(
+                bucket_config,
+                opendal_retry_layer(config),
+                opendal_circuit_breaker_layer(config),
+            )?,
+            bucket: destination_config.bucket.clone(),
+            destination_root,
+        }))
+    }
+
+    /// Copies the package at `source_url` to `destination_path` in the destination channel.
+    ///
+    /// Returns `false` if the package is not in the bucket or the copy failed, e.g. because of
+    /// missing permissions. The package has to be downloaded and uploaded in that case.
+    pub(crate) async fn copy(&self, source_url: &Url, destination_path: &str) -> bool {
+        if source_url.scheme() != "s3" || source_url.host_str() != Some(self.bucket.as_str()) {
+            return false;
+        }
+        let source_key = source_url.path().trim_start_matches('/');
+        let destination_key = if self.destination_root.is_empty() {
+            destination_path.to_string()
+        } else {
+            format!("{}/{}", self.destination_root, destination_path)
+        };
+        match self.operator.copy(source_key, &destination_key).await {
+            Ok(()) => {
+                tracing::debug!("Copied {} to {}", source_key, destination_key);
+                true
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Server-side copy of {} failed, downloading it instead: {}",
+                    source_url,
+                    e
+                );
+                false
+            }
+        }
+    }
+}
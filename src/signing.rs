@@ -0,0 +1,59 @@
+use ed25519_dalek::{Signer, SigningKey};
+use miette::IntoDiagnostic;
+use rattler_conda_types::PackageRecord;
+use std::{collections::BTreeMap, path::Path};
+
+/// Signs the repodata records of mirrored packages in the format used by conda-content-trust.
+pub struct PackageSigner {
+    key_id: String,
+    signing_key: SigningKey,
+}
+
+impl std::fmt::Debug for PackageSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PackageSigner")
+            .field("key_id", &self.key_id)
+            .field("signing_key", &"***")
+            .finish()
+    }
+}
+
+impl PackageSigner {
+    /// Reads a hex-encoded ed25519 private key (32 bytes) from `path`.
+    pub fn from_key_file(path: &Path, key_id: String) -> miette::Result<Self> {
+        let key_hex = std::fs::read_to_string(path)
+            .map_err(|e| miette::miette!("Could not read signing key {}: {}", path.display(), e))?;
+        let key_bytes: [u8; 32] = hex::decode(key_hex.trim())
+            .into_diagnostic()?
+            .try_into()
+            .map_err(|_| {
+                miette::miette!(
+                    "Signing key {} is not a 32 byte ed25519 key",
+                    path.display()
+                )
+            })?;
+        Ok(Self {
+            key_id,
+            signing_key: SigningKey::from_bytes(&key_bytes),
+        })
+    }
+
+    /// Signs the canonical serialization of `package_record` and returns the content of the
+    /// `.sig` sidecar file, i.e. `{"<key id>": {"signature": "<hex signature>"}}`.
+    pub fn sign(&self, package_record: &PackageRecord) -> miette::Result<Vec<u8>> {
+        let signature = self.signing_key.sign(&canonical_serialize(package_record)?);
+        let signatures = BTreeMap::from([(
+            self.key_id.as_str(),
+            BTreeMap::from([("signature", hex::encode(signature.to_bytes()))]),
+        )]);
+        serde_json::to_vec_pretty(&signatures).into_diagnostic()
+    }
+}
+
+/// Serializes `package_record` like conda-content-trust's `canonserialize`: sorted keys and an
+/// indentation of two spaces.
+fn canonical_serialize(package_record: &PackageRecord) -> miette::Result<Vec<u8>> {
+    // serde_json's `Map` is backed by a `BTreeMap`, so round-tripping through `Value` sorts all keys.
+    let value = serde_json::to_value(package_record).into_diagnostic()?;
+    serde_json::to_vec_pretty(&value).into_diagnostic()
+}
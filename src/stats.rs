@@ -0,0 +1,177 @@
+use indicatif::{HumanBytes, HumanDuration};
+use rattler_conda_types::Platform;
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Statistics about a mirror run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MirrorStats {
+    /// Number of packages that were copied to the destination.
+    pub packages_added: usize,
+    /// Number of packages that were deleted from the destination.
+    pub packages_deleted: usize,
+    /// Number of packages that already existed in the destination.
+    pub packages_skipped: usize,
+    /// Number of bytes that were downloaded from the source.
+    pub bytes_downloaded: u64,
+    /// Subdirs that were mirrored completely.
+    pub subdirs_mirrored: Vec<Platform>,
+    /// Failures that did not abort the run as `(item, error)` pairs.
+    pub failures: Vec<(String, String)>,
+    /// What changed in the individual subdirs, sorted by subdir.
+    pub subdir_diffs: Vec<MirrorSubdirDiff>,
+    /// Wall-clock time of the whole run.
+    #[serde(rename = "duration_secs", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+impl MirrorStats {
+    /// One-line summary of the totals of the run.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} deleted, {} skipped, {} downloaded in {}",
+            self.packages_added,
+            self.packages_deleted,
+            self.packages_skipped,
+            HumanBytes(self.bytes_downloaded),
+            HumanDuration(self.duration)
+        )
+    }
+
+    /// Whether the subdirs were mirrored to more than one destination.
+    fn has_multiple_destinations(&self) -> bool {
+        self.subdir_diffs
+            .iter()
+            .any(|diff| diff.destination != self.subdir_diffs[0].destination)
+    }
+
+    /// Table with the time, package counts and throughput of every subdir, and its destination if
+    /// there are several.
+    pub fn subdir_table(&self) -> String {
+        let multiple_destinations = self.has_multiple_destinations();
+        let mut table = format!(
+            "{:<16} {:>10} {:>8} {:>8} {:>12} {:>10}",
+            "subdir", "time", "added", "deleted", "transferred", "MB/s"
+        );
+        if multiple_destinations {
+            table.push_str("  destination");
+        }
+        table.push('\n');
+        for diff in &self.subdir_diffs {
+            let _ = write!(
+                table,
+                "{:<16} {:>10} {:>8} {:>8} {:>12} {:>10.2}",
+                diff.subdir.as_str(),
+                format!("{:.1}s", diff.duration.as_secs_f64()),
+                diff.added.len(),
+                diff.deleted.len(),
+                HumanBytes(diff.bytes_transferred).to_string(),
+                diff.throughput_mb_per_sec()
+            );
+            if multiple_destinations {
+                let _ = write!(table, "  {}", diff.destination);
+            }
+            table.push('\n');
+        }
+        table
+    }
+
+    /// The packages that were added or deleted, one `+ {subdir}/{filename}` or
+    /// `- {subdir}/{filename}` per line. With several destinations, the paths start with the
+    /// destination.
+    pub fn changed_packages(&self) -> String {
+        let multiple_destinations = self.has_multiple_destinations();
+        let mut changes = String::new();
+        for diff in &self.subdir_diffs {
+            let prefix = if multiple_destinations {
+                format!("{}/", diff.destination.trim_end_matches('/'))
+            } else {
+                String::new()
+            };
+            for filename in &diff.added {
+                let _ = writeln!(changes, "+ {}{}/{}", prefix, diff.subdir.as_str(), filename);
+            }
+            for filename in &diff.deleted {
+                let _ = writeln!(changes, "- {}{}/{}", prefix, diff.subdir.as_str(), filename);
+            }
+        }
+        changes
+    }
+}
+
+/// What changed in a single subdir during a mirror run.
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorSubdirDiff {
+    pub subdir: Platform,
+    /// The destination the subdir was mirrored to.
+    pub destination: String,
+    /// Filenames of the packages that were copied to the destination, sorted.
+    pub added: Vec<String>,
+    /// Filenames of the packages that were deleted from the destination, sorted.
+    pub deleted: Vec<String>,
+    /// Number of packages that already existed in the destination.
+    pub skipped: usize,
+    /// Number of bytes that were downloaded from the source.
+    pub bytes_transferred: u64,
+    /// Wall-clock time it took to mirror the subdir.
+    #[serde(rename = "duration_secs", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl MirrorSubdirDiff {
+    /// Effective download throughput in MB/s.
+    pub fn throughput_mb_per_sec(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.bytes_transferred as f64 / 1_000_000.0 / secs
+    }
+}
+
+/// Counters that are shared between the tasks of a mirror run.
+#[derive(Debug, Default)]
+pub(crate) struct MirrorCounters {
+    pub(crate) packages_added: AtomicUsize,
+    pub(crate) packages_deleted: AtomicUsize,
+    pub(crate) packages_skipped: AtomicUsize,
+    pub(crate) bytes_downloaded: AtomicU64,
+    /// Number of add and delete tasks that are currently running.
+    pub(crate) active_tasks: AtomicUsize,
+}
+
+/// Counts a task as active in [`MirrorCounters::active_tasks`] until it is dropped.
+pub(crate) struct ActiveTask<'a>(&'a MirrorCounters);
+
+impl<'a> ActiveTask<'a> {
+    pub(crate) fn new(counters: &'a MirrorCounters) -> Self {
+        counters.active_tasks.fetch_add(1, Ordering::Relaxed);
+        Self(counters)
+    }
+}
+
+impl Drop for ActiveTask<'_> {
+    fn drop(&mut self) {
+        self.0.active_tasks.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl MirrorCounters {
+    pub(crate) fn to_stats(&self) -> MirrorStats {
+        MirrorStats {
+            packages_added: self.packages_added.load(Ordering::Relaxed),
+            packages_deleted: self.packages_deleted.load(Ordering::Relaxed),
+            packages_skipped: self.packages_skipped.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use indicatif::HumanBytes;
+use miette::IntoDiagnostic;
+use rattler_conda_types::Platform;
+use sqlx::{
+    Connection, Row,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+};
+use std::{collections::HashMap, path::Path};
+
+use crate::stats::MirrorStats;
+
+const CREATE_MIRROR_RUNS_TABLE: &str = "CREATE TABLE IF NOT EXISTS mirror_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    source TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    packages_added INTEGER NOT NULL,
+    packages_deleted INTEGER NOT NULL,
+    bytes_transferred INTEGER NOT NULL,
+    errors INTEGER NOT NULL
+)";
+
+const CREATE_ABSENT_PACKAGES_TABLE: &str = "CREATE TABLE IF NOT EXISTS absent_packages (
+    destination TEXT NOT NULL,
+    subdir TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    first_absent TEXT NOT NULL,
+    absent_runs INTEGER NOT NULL,
+    PRIMARY KEY (destination, subdir, filename)
+)";
+
+/// A mirror run as recorded in the statistics database.
+#[derive(Debug, Clone)]
+pub struct MirrorRun {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub source: String,
+    pub destination: String,
+    pub packages_added: i64,
+    pub packages_deleted: i64,
+    pub bytes_transferred: i64,
+    pub errors: i64,
+}
+
+/// Opens the SQLite database at `path`, creating it and its tables if necessary.
+async fn connect(path: &Path) -> miette::Result<SqliteConnection> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    let mut connection = SqliteConnection::connect_with(&options)
+        .await
+        .map_err(|e| miette::miette!("Could not open stats database {}: {}", path.display(), e))?;
+    sqlx::query(CREATE_MIRROR_RUNS_TABLE)
+        .execute(&mut connection)
+        .await
+        .into_diagnostic()?;
+    sqlx::query(CREATE_ABSENT_PACKAGES_TABLE)
+        .execute(&mut connection)
+        .await
+        .into_diagnostic()?;
+    Ok(connection)
+}
+
+/// Records a finished mirror run in the statistics database at `path`.
+pub async fn record_run(
+    path: &Path,
+    started_at: DateTime<Utc>,
+    source: &str,
+    destination: &str,
+    stats: &MirrorStats,
+) -> miette::Result<()> {
+    let mut connection = connect(path).await?;
+    sqlx::query(
+        "INSERT INTO mirror_runs (started_at, finished_at, source, destination, packages_added, \
+         packages_deleted, bytes_transferred, errors) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(started_at.to_rfc3339())
+    .bind(Utc::now().to_rfc3339())
+    .bind(source)
+    .bind(destination)
+    .bind(stats.packages_added as i64)
+    .bind(stats.packages_deleted as i64)
+    .bind(stats.bytes_downloaded as i64)
+    .bind(stats.failures.len() as i64)
+    .execute(&mut connection)
+    .await
+    .into_diagnostic()?;
+    connection.close().await.into_diagnostic()?;
+    tracing::info!("Recorded mirror run in {}", path.display());
+    Ok(())
+}
+
+/// Records that the packages in `absent` are missing in the source of `subdir` and returns the
+/// ones that have been missing for at least `runs` consecutive runs, which may be deleted now.
+///
+/// Packages that are no longer missing are forgotten, so that they start over when they go
+/// missing again.
+pub(crate) async fn update_absent_packages(
+    path: &Path,
+    destination: &str,
+    subdir: Platform,
+    absent: &[String],
+    runs: usize,
+) -> miette::Result<Vec<String>> {
+    let mut connection = connect(path).await?;
+    let mut transaction = connection.begin().await.into_diagnostic()?;
+    let previously_absent = sqlx::query(
+        "SELECT filename, absent_runs FROM absent_packages WHERE destination = ? AND subdir = ?",
+    )
+    .bind(destination)
+    .bind(subdir.as_str())
+    .fetch_all(&mut *transaction)
+    .await
+    .into_diagnostic()?
+    .into_iter()
+    .map(|row| {
+        let filename: String = row.try_get("filename").into_diagnostic()?;
+        let absent_runs: i64 = row.try_get("absent_runs").into_diagnostic()?;
+        Ok((filename, absent_runs as usize))
+    })
+    .collect::<miette::Result<HashMap<_, _>>>()?;
+
+    for filename in previously_absent
+        .keys()
+        .filter(|filename| !absent.contains(*filename))
+    {
+        delete_absent_package(&mut transaction, destination, subdir, filename).await?;
+    }
+
+    let mut to_delete = Vec::new();
+    for filename in absent {
+        let absent_runs = previously_absent.get(filename).copied().unwrap_or(0) + 1;
+        if absent_runs >= runs {
+            delete_absent_package(&mut transaction, destination, subdir, filename).await?;
+            to_delete.push(filename.clone());
+        } else {
+            sqlx::query(
+                "INSERT INTO absent_packages (destination, subdir, filename, first_absent, \
+                 absent_runs) VALUES (?, ?, ?, ?, ?) ON CONFLICT (destination, subdir, filename) \
+                 DO UPDATE SET absent_runs = excluded.absent_runs",
+            )
+            .bind(destination)
+            .bind(subdir.as_str())
+            .bind(filename)
+            .bind(Utc::now().to_rfc3339())
+            .bind(absent_runs as i64)
+            .execute(&mut *transaction)
+            .await
+            .into_diagnostic()?;
+        }
+    }
+    transaction.commit().await.into_diagnostic()?;
+    connection.close().await.into_diagnostic()?;
+    Ok(to_delete)
+}
+
+async fn delete_absent_package(
+    connection: &mut SqliteConnection,
+    destination: &str,
+    subdir: Platform,
+    filename: &str,
+) -> miette::Result<()> {
+    sqlx::query(
+        "DELETE FROM absent_packages WHERE destination = ? AND subdir = ? AND filename = ?",
+    )
+    .bind(destination)
+    .bind(subdir.as_str())
+    .bind(filename)
+    .execute(connection)
+    .await
+    .into_diagnostic()?;
+    Ok(())
+}
+
+/// Returns the last `limit` mirror runs from the statistics database at `path`, newest first.
+pub async fn run_history(path: &Path, limit: u32) -> miette::Result<Vec<MirrorRun>> {
+    let mut connection = connect(path).await?;
+    let rows = sqlx::query(
+        "SELECT id, started_at, finished_at, source, destination, packages_added, \
+         packages_deleted, bytes_transferred, errors FROM mirror_runs ORDER BY id DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .into_diagnostic()?;
+    connection.close().await.into_diagnostic()?;
+
+    rows.into_iter()
+        .map(|row| {
+            let parse_time = |column: &str| -> miette::Result<DateTime<Utc>> {
+                let time: String = row.try_get(column).into_diagnostic()?;
+                Ok(DateTime::parse_from_rfc3339(&time)
+                    .into_diagnostic()?
+                    .with_timezone(&Utc))
+            };
+            Ok(MirrorRun {
+                id: row.try_get("id").into_diagnostic()?,
+                started_at: parse_time("started_at")?,
+                finished_at: parse_time("finished_at")?,
+                source: row.try_get("source").into_diagnostic()?,
+                destination: row.try_get("destination").into_diagnostic()?,
+                packages_added: row.try_get("packages_added").into_diagnostic()?,
+                packages_deleted: row.try_get("packages_deleted").into_diagnostic()?,
+                bytes_transferred: row.try_get("bytes_transferred").into_diagnostic()?,
+                errors: row.try_get("errors").into_diagnostic()?,
+            })
+        })
+        .collect()
+}
+
+/// Formats mirror runs as a table for printing.
+pub fn format_history(runs: &[MirrorRun]) -> String {
+    let mut table = format!(
+        "{:>5} {:<20} {:>9} {:>8} {:>8} {:>12} {:>6}  {}\n",
+        "id",
+        "started",
+        "duration",
+        "added",
+        "deleted",
+        "transferred",
+        "errors",
+        "source -> destination"
+    );
+    for run in runs {
+        let duration = (run.finished_at - run.started_at).num_seconds();
+        table.push_str(&format!(
+            "{:>5} {:<20} {:>9} {:>8} {:>8} {:>12} {:>6}  {} -> {}\n",
+            run.id,
+            run.started_at.format("%Y-%m-%d %H:%M:%S"),
+            format!("{duration}s"),
+            run.packages_added,
+            run.packages_deleted,
+            HumanBytes(run.bytes_transferred.max(0) as u64).to_string(),
+            run.errors,
+            run.source,
+            run.destination
+        ));
+    }
+    table
+}
@@ -0,0 +1,100 @@
+use miette::IntoDiagnostic;
+use rattler_conda_types::{PackageRecord, package::ArchiveType};
+use std::{collections::HashMap, process::Stdio};
+use tokio::{io::AsyncWriteExt, process::Command};
+use url::Url;
+
+/// Renames the packages using `command`, see `package_name_transform_command`.
+///
+/// The command is run once with all filenames on stdin, one per line, and has to print the new
+/// filenames in the same order. Packages are only renamed in the destination, the URLs still
+/// point to the original files in the source.
+pub(crate) async fn transform_package_names(
+    command: &str,
+    packages: HashMap<String, (PackageRecord, Url)>,
+) -> miette::Result<HashMap<String, (PackageRecord, Url)>> {
+    let mut packages = packages.into_iter().collect::<Vec<_>>();
+    packages.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let input = packages
+        .iter()
+        .map(|(filename, _)| format!("{filename}\n"))
+        .collect::<String>();
+    let output = run_transform_command(command, input).await?;
+    let new_filenames = output.lines().collect::<Vec<_>>();
+    if new_filenames.len() != packages.len() {
+        return Err(miette::miette!(
+            "Package name transform `{}` printed {} lines for {} packages",
+            command,
+            new_filenames.len(),
+            packages.len()
+        ));
+    }
+
+    let mut transformed = HashMap::with_capacity(packages.len());
+    let mut original_filenames = HashMap::new();
+    for ((filename, package), new_filename) in packages.into_iter().zip(new_filenames) {
+        // The archive type must not change, the file is uploaded as is.
+        let new_filename = if ArchiveType::try_from(new_filename)
+            == ArchiveType::try_from(&filename)
+        {
+            new_filename.to_string()
+        } else {
+            tracing::warn!(
+                "Package name transform renamed {} to {}, which is not a conda package filename with the same extension. Keeping the original name",
+                filename,
+                new_filename
+            );
+            filename.clone()
+        };
+        if let Some(other) = original_filenames.insert(new_filename.clone(), filename.clone()) {
+            return Err(miette::miette!(
+                "Package name transform renamed both {} and {} to {}",
+                other,
+                filename,
+                new_filename
+            ));
+        }
+        transformed.insert(new_filename, package);
+    }
+    Ok(transformed)
+}
+
+async fn run_transform_command(command: &str, input: String) -> miette::Result<String> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            miette::miette!("Could not run package name transform `{}`: {}", command, e)
+        })?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Write concurrently with reading stdout, the command may not consume all input up front.
+    let write_input = tokio::spawn(async move {
+        stdin.write_all(input.as_bytes()).await?;
+        stdin.shutdown().await
+    });
+    let output = child.wait_with_output().await.into_diagnostic()?;
+    if !output.status.success() {
+        return Err(miette::miette!(
+            "Package name transform `{}` failed: {}",
+            command,
+            output.status
+        ));
+    }
+    write_input.await.into_diagnostic()?.into_diagnostic()?;
+    String::from_utf8(output.stdout).into_diagnostic()
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
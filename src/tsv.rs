@@ -0,0 +1,52 @@
+use rattler_conda_types::{PackageRecord, Platform};
+use std::time::Duration;
+
+/// Header of the `--output-tsv` output.
+pub(crate) const TSV_HEADER: &str = "action\tsubdir\tfilename\tsize_bytes\tsha256\tduration_ms";
+
+/// What happened to a package during a mirror run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PackageAction {
+    Add,
+    Delete,
+    Skip,
+}
+
+impl PackageAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PackageAction::Add => "add",
+            PackageAction::Delete => "delete",
+            PackageAction::Skip => "skip",
+        }
+    }
+}
+
+/// Prints a row of the `--output-tsv` output to stdout.
+///
+/// The size and hash are left empty if they are not known, e.g. for deleted packages.
+pub(crate) fn print_tsv_row(
+    action: PackageAction,
+    subdir: Platform,
+    filename: &str,
+    record: Option<&PackageRecord>,
+    duration: Duration,
+) {
+    let size = record
+        .and_then(|record| record.size)
+        .map(|size| size.to_string())
+        .unwrap_or_default();
+    let sha256 = record
+        .and_then(|record| record.sha256)
+        .map(|sha256| format!("{sha256:x}"))
+        .unwrap_or_default();
+    println!(
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        action.as_str(),
+        subdir.as_str(),
+        filename,
+        size,
+        sha256,
+        duration.as_millis()
+    );
+}
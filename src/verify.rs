@@ -0,0 +1,144 @@
+use futures::{StreamExt, TryStreamExt, stream};
+use miette::IntoDiagnostic;
+use opendal::Operator;
+use rattler_conda_types::{PackageRecord, Platform, RepoData};
+use rattler_digest::Sha256Hash;
+use sha2::{Digest, Sha256};
+
+/// Number of packages that are read from the destination at the same time.
+const MAX_PARALLEL_VERIFICATIONS: usize = 10;
+
+/// Result of checking the packages in the repodata of the destination against their records.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Number of packages whose size and SHA256 match the repodata.
+    pub verified: usize,
+    /// Packages listed in the repodata that don't exist in the destination, as `subdir/filename`.
+    pub missing: Vec<String>,
+    /// Packages whose size or SHA256 doesn't match the repodata, with what differs.
+    pub corrupted: Vec<(String, String)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty()
+    }
+
+    pub(crate) fn merge(&mut self, other: VerifyReport) {
+        self.verified += other.verified;
+        self.missing.extend(other.missing);
+        self.corrupted.extend(other.corrupted);
+    }
+}
+
+enum PackageState {
+    Verified,
+    Missing,
+    Corrupted(String),
+}
+
+/// Reads every package listed in the repodata of `subdir` from the destination and compares its
+/// size and SHA256 with the record.
+pub(crate) async fn verify_subdir(op: &Operator, subdir: Platform) -> miette::Result<VerifyReport> {
+    let repodata_path = format!("{}/repodata.json", subdir.as_str());
+    let repodata_bytes = op.read(&repodata_path).await.into_diagnostic()?.to_vec();
+    let repodata: RepoData = serde_json::from_slice(&repodata_bytes)
+        .map_err(|e| miette::miette!("Could not parse {}: {}", repodata_path, e))?;
+    tracing::info!(
+        "Verifying {} packages in {}",
+        repodata.packages.len() + repodata.conda_packages.len(),
+        subdir
+    );
+
+    let mut results = stream::iter(repodata.packages.iter().chain(&repodata.conda_packages))
+        .map(|(filename, record)| async move {
+            let path = format!("{}/{}", subdir.as_str(), filename);
+            verify_package(op, &path, record)
+                .await
+                .map(|state| (path, state))
+        })
+        .buffer_unordered(MAX_PARALLEL_VERIFICATIONS);
+
+    let mut report = VerifyReport::default();
+    while let Some(result) = results.next().await {
+        match result? {
+            (_, PackageState::Verified) => report.verified += 1,
+            (path, PackageState::Missing) => report.missing.push(path),
+            (path, PackageState::Corrupted(reason)) => report.corrupted.push((path, reason)),
+        }
+    }
+    report.missing.sort();
+    report.corrupted.sort();
+    Ok(report)
+}
+
+async fn verify_package(
+    op: &Operator,
+    path: &str,
+    record: &PackageRecord,
+) -> miette::Result<PackageState> {
+    let (size, digest) = match read_size_and_digest(op, path).await {
+        Ok(result) => result,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(PackageState::Missing),
+        Err(e) => return Err(e).into_diagnostic(),
+    };
+
+    if let Some(expected_size) = record.size
+        && expected_size != size
+    {
+        return Ok(PackageState::Corrupted(format!(
+            "size {size} != {expected_size}"
+        )));
+    }
+    match record.sha256 {
+        Some(expected_digest) if expected_digest != digest => Ok(PackageState::Corrupted(format!(
+            "SHA256 {digest:x} != {expected_digest:x}"
+        ))),
+        Some(_) => Ok(PackageState::Verified),
+        None => {
+            tracing::warn!(
+                "{} has no SHA256 in the repodata, only its size was checked",
+                path
+            );
+            Ok(PackageState::Verified)
+        }
+    }
+}
+
+/// Streams the package from the destination, so that large packages are not kept in memory.
+async fn read_size_and_digest(op: &Operator, path: &str) -> std::io::Result<(u64, Sha256Hash)> {
+    let mut chunks = op.reader(path).await?.into_bytes_stream(..).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = chunks.try_next().await? {
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+    Ok((size, hasher.finalize()))
+}
+
+/// Formats the report for printing.
+pub fn format_verify_report(report: &VerifyReport) -> String {
+    let mut output = format!(
+        "{:<10} {:>8}\n{:<10} {:>8}\n{:<10} {:>8}\n",
+        "Verified:",
+        report.verified,
+        "Missing:",
+        report.missing.len(),
+        "Corrupted:",
+        report.corrupted.len(),
+    );
+    if !report.missing.is_empty() {
+        output.push_str("\nMissing packages:\n");
+        for path in &report.missing {
+            output.push_str(&format!("  {path}\n"));
+        }
+    }
+    if !report.corrupted.is_empty() {
+        output.push_str("\nCorrupted packages:\n");
+        for (path, reason) in &report.corrupted {
+            output.push_str(&format!("  {path}: {reason}\n"));
+        }
+    }
+    output
+}
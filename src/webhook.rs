@@ -0,0 +1,93 @@
+use http::header::CONTENT_TYPE;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Serialize;
+use std::time::Duration;
+use url::Url;
+
+use crate::{config::CondaMirrorConfig, stats::MirrorStats};
+
+/// Timeout of the webhook request, a slow receiver must not hold up the mirror.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    status: &'static str,
+    sources: &'a [String],
+    destination: &'a str,
+    stats: Option<&'a MirrorStats>,
+    errors: Vec<String>,
+}
+
+/// Reports the outcome of a mirror run to `webhook-url`.
+pub(crate) struct Webhook {
+    url: Url,
+    on_failure_only: bool,
+    sources: Vec<String>,
+    destination: String,
+}
+
+impl Webhook {
+    pub(crate) fn new(url: Url, config: &CondaMirrorConfig) -> Self {
+        Self {
+            url,
+            on_failure_only: config.webhook_on_failure_only,
+            sources: config.sources().map(ToString::to_string).collect(),
+            destination: config.destination.to_string(),
+        }
+    }
+
+    /// POSTs the result of the run as JSON. Delivery failures are only logged.
+    pub(crate) async fn notify(
+        &self,
+        client: &ClientWithMiddleware,
+        result: &miette::Result<MirrorStats>,
+    ) {
+        let payload = match result {
+            Ok(_) if self.on_failure_only => return,
+            Ok(stats) => WebhookPayload {
+                status: "success",
+                sources: &self.sources,
+                destination: &self.destination,
+                stats: Some(stats),
+                errors: stats
+                    .failures
+                    .iter()
+                    .map(|(item, error)| format!("{item}: {error}"))
+                    .collect(),
+            },
+            Err(e) => WebhookPayload {
+                status: "failure",
+                sources: &self.sources,
+                destination: &self.destination,
+                stats: None,
+                errors: e.chain().map(ToString::to_string).collect(),
+            },
+        };
+
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("Could not serialize webhook payload: {}", e);
+                return;
+            }
+        };
+        let request = client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send();
+        // The timeout covers the retries of the client as well.
+        match tokio::time::timeout(WEBHOOK_TIMEOUT, request).await {
+            Ok(Ok(response)) => match response.error_for_status() {
+                Ok(_) => tracing::info!("Sent {} notification to {}", payload.status, self.url),
+                Err(e) => tracing::warn!("Webhook {} failed: {}", self.url, e),
+            },
+            Ok(Err(e)) => tracing::warn!("Could not send webhook to {}: {}", self.url, e),
+            Err(_) => tracing::warn!(
+                "Webhook {} did not respond within {}s",
+                self.url,
+                WEBHOOK_TIMEOUT.as_secs()
+            ),
+        }
+    }
+}